@@ -1,7 +1,36 @@
-use clap::{Arg, App, SubCommand};
+use clap::{Arg, App, AppSettings, Shell, SubCommand};
+
+use std::io::Write;
+
+pub const BIN_NAME: &str = "yadisk-client";
+
+/// Subcommands taking remote paths, completed by `__complete`
+const REMOTE_PATH_COMMANDS: &[&str] = &[
+    "cat", "delete", "download", "du", "dupes", "find", "list", "publish", "tree", "unpublish", "upload", "verify",
+];
+
+/// Global options with a value, completion scripts skip the value looking for the subcommand
+const GLOBAL_VALUE_OPTIONS: &[&str] = &[
+    "-t", "--oauth_token", "-u", "--url", "-p", "--proxy", "-c", "--config", "--log-file", "--key-file",
+];
 
 pub fn init_cli() -> clap::ArgMatches<'static>{
-    App::new("yadisk-client")
+    build_cli().get_matches()
+}
+
+pub fn build_cli() -> App<'static, 'static> {
+    visible_cli().subcommand(SubCommand::with_name("__complete")
+        .setting(AppSettings::Hidden)
+        .about("Print remote paths starting with WORD, for completion scripts")
+        .arg(Arg::with_name("word")
+            .default_value("/")
+            .index(1)))
+}
+
+/// All subcommands but `__complete`, which completion scripts must not see:
+/// clap's bash generator takes "__" for its own separator
+fn visible_cli() -> App<'static, 'static> {
+    App::new(BIN_NAME)
                             .version("0.1.0")
                             .author("Mikhail B. <m@mdbx.ru>")
                             .about("Does some things with Yandex Disk")
@@ -29,28 +58,95 @@ pub fn init_cli() -> clap::ArgMatches<'static>{
                                 .value_name("CONFIG")
                                 .help("Get configuration from file")
                                 .takes_value(true))
+                            .arg(Arg::with_name("verbose")
+                                .short("v")
+                                .long("verbose")
+                                .multiple(true)
+                                .help("Increase logging verbosity: -v for debug, -vv for trace"))
+                            .arg(Arg::with_name("quiet")
+                                .short("q")
+                                .long("quiet")
+                                .conflicts_with("verbose")
+                                .help("Log errors only"))
+                            .arg(Arg::with_name("log_file")
+                                .long("log-file")
+                                .value_name("LOG_FILE")
+                                .help("Also write log records to file")
+                                .takes_value(true))
+                            .arg(Arg::with_name("key_file")
+                                .long("key-file")
+                                .value_name("KEY_FILE")
+                                .help("Encryption key file, see keygen. Passphrase is taken from YADISK_PASSPHRASE otherwise")
+                                .takes_value(true))
+                            .arg(Arg::with_name("offline")
+                                .long("offline")
+                                .help("Browse and search listings cached by earlier runs, without any request"))
+                            .arg(Arg::with_name("no_cache")
+                                .long("no-cache")
+                                .conflicts_with("offline")
+                                .help("Don't use cached listings, even when the Disk hasn't changed"))
+                            .arg(Arg::with_name("dry_run")
+                                .short("n")
+                                .long("dry-run")
+                                .conflicts_with("offline")
+                                .help("Print what would be created, overwritten, moved or deleted, change nothing"))
+                            .subcommand(SubCommand::with_name("keygen")
+                                .about("Generate a random encryption key file")
+                                .arg(Arg::with_name("path")
+                                    .help("Key file to create, an existing one is never overwritten")
+                                    .required(true)
+                                    .index(1)))
                             .subcommand(SubCommand::with_name("login")
                                 .about("Authorize this application to access Yandex Disk. You will be provided with url to grant privileges. Then you will be asked for an authorization code"))
                             .subcommand(SubCommand::with_name("info")
-                                .about("Get general information about yandex disk account"))
+                                .about("Get general information about yandex disk account")
+                                .arg(Arg::with_name("warn_at")
+                                    .long("warn-at")
+                                    .value_name("PERCENT")
+                                    .help("Exit with code 3 when used space reaches PERCENT, e.g. 90%"))
+                                .arg(Arg::with_name("crit_at")
+                                    .long("crit-at")
+                                    .value_name("PERCENT")
+                                    .help("Exit with code 4 when used space reaches PERCENT, e.g. 95%")))
                             .subcommand(SubCommand::with_name("last")
                                 .about("Get last uploaded file list")
                                 .arg(Arg::with_name("limit")
                                     .short("l")
                                     .long("limit")
-                                    .default_value("5")))
+                                    .default_value("5"))
+                                .arg(Arg::with_name("fields")
+                                    .long("fields")
+                                    .value_name("LIST")
+                                    .help("Comma separated attributes to request, e.g. name,size,md5. Default is what is shown")))
                             .subcommand(SubCommand::with_name("download")
                                 .about("Download single file")
                                 .arg(Arg::with_name("path")
                                     .help("File name with full path to download")
                                     .index(1))
                                 .arg(Arg::with_name("target")
-                                    .help("Target path file will be saved to")
-                                    .index(2)))
+                                    .help("Target path file will be saved to. Use '-' for STDOUT")
+                                    .index(2))
+                                .arg(Arg::with_name("no_verify")
+                                    .long("no-verify")
+                                    .help("Don't compare checksums of transferred data with the ones reported by the Disk"))
+                                .arg(Arg::with_name("retries")
+                                    .long("retries")
+                                    .value_name("N")
+                                    .help("Repeat transfer up to N times if checksums don't match")
+                                    .default_value("0")))
+                            .subcommand(SubCommand::with_name("cat")
+                                .about("Write remote file to STDOUT")
+                                .arg(Arg::with_name("path")
+                                    .help("File name with full path to print")
+                                    .required(true)
+                                    .index(1))
+                                .arg(Arg::with_name("no_verify")
+                                    .long("no-verify")
+                                    .help("Don't compare checksums of printed data with the ones reported by the Disk")))
                             .subcommand(SubCommand::with_name("upload")
-                                .about("Upload single file")
+                                .about("Upload file, or directory with --recursive")
                                 .arg(Arg::with_name("path")
-                                    .help("Local filename with full path")
+                                    .help("Local filename with full path. Use '-' for STDIN")
                                     .index(1))
                                 .arg(Arg::with_name("remote")
                                     .help("Remote path file will be saved to")
@@ -59,7 +155,144 @@ pub fn init_cli() -> clap::ArgMatches<'static>{
                                     .help("Overwrite file if it already exists on remote path. true|false")
                                     .long("overwrite")
                                     .value_name("overwrite")
-                                    .default_value("false")))
+                                    .default_value("false"))
+                                .arg(Arg::with_name("recursive")
+                                    .help("Upload local directory with everything inside")
+                                    .short("r")
+                                    .long("recursive"))
+                                .arg(Arg::with_name("force")
+                                    .help("Don't check free space and max file size before upload")
+                                    .long("force"))
+                                .arg(Arg::with_name("split")
+                                    .long("split")
+                                    .help("Upload files over max file size as numbered parts with a manifest, downloads reassemble them"))
+                                .arg(Arg::with_name("split_size")
+                                    .long("split-size")
+                                    .value_name("SIZE")
+                                    .help("Part size for --split, e.g. 10G. Default is max file size of the Disk")
+                                    .requires("split"))
+                                .arg(Arg::with_name("parallel")
+                                    .long("parallel")
+                                    .value_name("N")
+                                    .help("Parts uploaded at once with --split")
+                                    .default_value("4"))
+                                .arg(Arg::with_name("encrypt")
+                                    .long("encrypt")
+                                    .help("Encrypt content before upload, name gets .ydenc extension. Downloads decrypt it"))
+                                .arg(Arg::with_name("encrypt_names")
+                                    .long("encrypt-names")
+                                    .help("Encrypt file and directory names too")
+                                    .requires("encrypt"))
+                                .arg(Arg::with_name("compress")
+                                    .long("compress")
+                                    .value_name("METHOD")
+                                    .possible_values(&["zstd", "gzip"])
+                                    .help("Compress content on the fly, name gets .zst or .gz extension. Downloads decompress it. Extensions in compress_exclude of the config are left as is"))
+                                .arg(Arg::with_name("dedupe")
+                                    .long("dedupe")
+                                    .help("Copy files the Disk already has with the same content on the server instead of uploading them")
+                                    .conflicts_with_all(&["encrypt", "compress"]))
+                                .arg(Arg::with_name("from_url")
+                                    .help("Let Yandex Disk fetch the file from URL by itself. Remote path is the only positional argument then")
+                                    .long("from-url")
+                                    .value_name("URL")
+                                    .takes_value(true))
+                                .arg(Arg::with_name("from_url_list")
+                                    .help("Import every URL from file, one 'URL [remote path]' per line. Positional argument is the remote directory for lines without remote path")
+                                    .long("from-url-list")
+                                    .value_name("FILE")
+                                    .conflicts_with("from_url")
+                                    .takes_value(true))
+                                .arg(Arg::with_name("no_verify")
+                                    .long("no-verify")
+                                    .help("Don't compare checksums of transferred data with the ones reported by the Disk"))
+                                .arg(Arg::with_name("retries")
+                                    .long("retries")
+                                    .value_name("N")
+                                    .help("Repeat transfer up to N times if checksums don't match")
+                                    .default_value("0")))
+                            .subcommand(SubCommand::with_name("transfer")
+                                .about("Copy file, or directory with --recursive, from the Disk of one profile to another without storing it locally")
+                                .arg(Arg::with_name("from_profile")
+                                    .long("from-profile")
+                                    .value_name("PROFILE:PATH")
+                                    .help("Source profile of [profiles.PROFILE] in config and path on its Disk")
+                                    .required(true))
+                                .arg(Arg::with_name("to_profile")
+                                    .long("to-profile")
+                                    .value_name("PROFILE:PATH")
+                                    .help("Target profile and path")
+                                    .required(true))
+                                .arg(Arg::with_name("recursive")
+                                    .help("Transfer directory with everything inside")
+                                    .short("r")
+                                    .long("recursive"))
+                                .arg(Arg::with_name("method")
+                                    .long("method")
+                                    .possible_values(&["stream", "publish", "auto"])
+                                    .help("Stream through this machine, or publish source files and save them to the target Disk on the server. auto publishes files of 64 MiB and more")
+                                    .default_value("stream"))
+                                .arg(Arg::with_name("overwrite")
+                                    .help("Replace target files with other content. true|false")
+                                    .long("overwrite")
+                                    .value_name("overwrite")
+                                    .default_value("false"))
+                                .arg(Arg::with_name("force")
+                                    .help("Don't check free space and max file size of the target Disk")
+                                    .long("force"))
+                                .arg(Arg::with_name("no_verify")
+                                    .long("no-verify")
+                                    .help("Don't compare md5 and sha256 of target files with the source ones"))
+                                .arg(Arg::with_name("retries")
+                                    .long("retries")
+                                    .value_name("N")
+                                    .help("Repeat transfer up to N times if checksums don't match")
+                                    .default_value("0")))
+                            .subcommand(SubCommand::with_name("batch")
+                                .about("Run operations listed in a file, one per line as words or JSON, and write a result log")
+                                .arg(Arg::with_name("file")
+                                    .help("Batch file: 'mkdir PATH', 'upload LOCAL REMOTE', 'download REMOTE [LOCAL]', 'mv FROM TO', 'cp FROM TO', 'rm PATH', 'publish PATH' or {\"op\": \"mv\", \"from\": ..., \"to\": ...}. Use '-' for STDIN")
+                                    .default_value("-")
+                                    .index(1))
+                                .arg(Arg::with_name("jobs")
+                                    .short("j")
+                                    .long("jobs")
+                                    .value_name("N")
+                                    .help("Operations run at once. mkdir waits for the ones before it")
+                                    .default_value("1"))
+                                .arg(Arg::with_name("stop_on_error")
+                                    .long("stop-on-error")
+                                    .help("Start nothing after a failure, the rest is logged as skipped"))
+                                .arg(Arg::with_name("results")
+                                    .long("results")
+                                    .value_name("FILE")
+                                    .help("Write result log to FILE instead of STDOUT. Run it as a batch to repeat what didn't succeed"))
+                                .arg(Arg::with_name("overwrite")
+                                    .help("Overwrite existing files on upload, mv and cp. true|false")
+                                    .long("overwrite")
+                                    .value_name("overwrite")
+                                    .default_value("false"))
+                                .arg(Arg::with_name("force")
+                                    .help("Don't check free space and max file size before uploads")
+                                    .long("force"))
+                                .arg(Arg::with_name("no_verify")
+                                    .long("no-verify")
+                                    .help("Don't compare checksums of transferred data with the ones reported by the Disk"))
+                                .arg(Arg::with_name("retries")
+                                    .long("retries")
+                                    .value_name("N")
+                                    .help("Repeat transfer up to N times if checksums don't match")
+                                    .default_value("0")))
+                            .subcommand(SubCommand::with_name("verify")
+                                .about("Compare local file or directory tree with remote one by size and checksums")
+                                .arg(Arg::with_name("local")
+                                    .help("Local file or directory")
+                                    .required(true)
+                                    .index(1))
+                                .arg(Arg::with_name("remote")
+                                    .help("Remote file or directory")
+                                    .required(true)
+                                    .index(2)))
                             .subcommand(SubCommand::with_name("delete")
                                     .about("Delete file on remote side")
                                     .arg(Arg::with_name("remote")
@@ -74,11 +307,250 @@ pub fn init_cli() -> clap::ArgMatches<'static>{
                                 .arg(Arg::with_name("path")
                                     .help("Sets the base path to fetch listing of. Default is root")
                                     .default_value("/")
-                                    .index(1)))
+                                    .index(1))
+                                .arg(Arg::with_name("fields")
+                                    .long("fields")
+                                    .value_name("LIST")
+                                    .help("Comma separated attributes to request, e.g. name,size,md5. Default is what is shown")))
+                            .subcommand(SubCommand::with_name("du")
+                                .about("Show total size of remote folders")
+                                .arg(Arg::with_name("path")
+                                    .help("Remote folder. Default is root")
+                                    .default_value("/")
+                                    .index(1))
+                                .arg(Arg::with_name("max_depth")
+                                    .short("d")
+                                    .long("max-depth")
+                                    .value_name("N")
+                                    .help("Show folders only N or fewer levels below the given one"))
+                                .arg(Arg::with_name("sort")
+                                    .long("sort")
+                                    .possible_values(&["size", "name"])
+                                    .default_value("size"))
+                                .arg(Arg::with_name("bytes")
+                                    .short("b")
+                                    .long("bytes")
+                                    .help("Print sizes in bytes"))
+                                .arg(Arg::with_name("flat")
+                                    .long("flat")
+                                    .help("Use flat list of all Disk files, faster for very large trees. Empty folders are not shown")))
+                            .subcommand(SubCommand::with_name("tree")
+                                .about("Show remote folder as a tree")
+                                .arg(Arg::with_name("path")
+                                    .help("Remote folder. Default is root")
+                                    .default_value("/")
+                                    .index(1))
+                                .arg(Arg::with_name("max_depth")
+                                    .short("d")
+                                    .long("max-depth")
+                                    .value_name("N")
+                                    .help("Descend at most N levels"))
+                                .arg(Arg::with_name("size")
+                                    .short("s")
+                                    .long("size")
+                                    .help("Print sizes of files and folders"))
+                                .arg(Arg::with_name("flat")
+                                    .long("flat")
+                                    .help("Use flat list of all Disk files, faster for very large trees. Empty folders are not shown")))
+                            .subcommand(SubCommand::with_name("find")
+                                .about("Search remote files by name, size, dates and types")
+                                .arg(Arg::with_name("path")
+                                    .help("Remote folder to search in. Default is root")
+                                    .default_value("/")
+                                    .index(1))
+                                .arg(Arg::with_name("name")
+                                    .long("name")
+                                    .value_name("GLOB")
+                                    .help("Name matches glob pattern, e.g. '*.jpg'"))
+                                .arg(Arg::with_name("regex")
+                                    .long("regex")
+                                    .value_name("REGEX")
+                                    .help("Name matches regular expression"))
+                                .arg(Arg::with_name("type")
+                                    .long("type")
+                                    .value_name("TYPE")
+                                    .possible_values(&["file", "dir"]))
+                                .arg(Arg::with_name("min_size")
+                                    .long("min-size")
+                                    .value_name("SIZE")
+                                    .help("At least SIZE bytes, K/M/G/T suffixes are allowed"))
+                                .arg(Arg::with_name("max_size")
+                                    .long("max-size")
+                                    .value_name("SIZE")
+                                    .help("At most SIZE bytes, K/M/G/T suffixes are allowed"))
+                                .arg(Arg::with_name("created_after")
+                                    .long("created-after")
+                                    .value_name("DATE")
+                                    .help("YYYY-MM-DD or RFC 3339 time"))
+                                .arg(Arg::with_name("created_before")
+                                    .long("created-before")
+                                    .value_name("DATE"))
+                                .arg(Arg::with_name("modified_after")
+                                    .long("modified-after")
+                                    .value_name("DATE"))
+                                .arg(Arg::with_name("modified_before")
+                                    .long("modified-before")
+                                    .value_name("DATE"))
+                                .arg(Arg::with_name("mime_type")
+                                    .long("mime-type")
+                                    .value_name("GLOB")
+                                    .help("MIME type matches glob pattern, e.g. 'image/*'"))
+                                .arg(Arg::with_name("media_type")
+                                    .long("media-type")
+                                    .value_name("TYPE")
+                                    .help("Media type detected by the Disk: image, video, audio, document, ..."))
+                                .arg(Arg::with_name("md5")
+                                    .long("md5")
+                                    .value_name("HASH"))
+                                .arg(Arg::with_name("flat")
+                                    .long("flat")
+                                    .help("Search the flat list of all Disk files instead of walking folders. Finds files only"))
+                                .arg(Arg::with_name("format")
+                                    .long("format")
+                                    .possible_values(&["plain", "long", "json"])
+                                    .default_value("plain"))
+                                .arg(Arg::with_name("fields")
+                                    .long("fields")
+                                    .value_name("LIST")
+                                    .help("Comma separated attributes to request, e.g. name,size,md5. Default is what filters and format need, everything for json"))
+                                .arg(Arg::with_name("delete")
+                                    .long("delete")
+                                    .help("Move found resources to trash")
+                                    .conflicts_with_all(&["download_to", "move_to"]))
+                                .arg(Arg::with_name("permanently")
+                                    .long("permanently")
+                                    .requires("delete")
+                                    .help("Delete found resources for good instead of moving to trash"))
+                                .arg(Arg::with_name("download_to")
+                                    .long("download-to")
+                                    .value_name("DIR")
+                                    .help("Download found files into local directory")
+                                    .conflicts_with("move_to"))
+                                .arg(Arg::with_name("no_verify")
+                                    .long("no-verify")
+                                    .requires("download_to")
+                                    .help("Don't compare checksums of downloaded data with the ones reported by the Disk"))
+                                .arg(Arg::with_name("retries")
+                                    .long("retries")
+                                    .value_name("N")
+                                    .requires("download_to")
+                                    .help("Repeat download up to N times if checksums don't match"))
+                                .arg(Arg::with_name("move_to")
+                                    .long("move-to")
+                                    .value_name("DIR")
+                                    .help("Move found resources into remote directory")))
+                            .subcommand(SubCommand::with_name("dupes")
+                                .about("Find files with the same content and report wasted space")
+                                .arg(Arg::with_name("path")
+                                    .help("Remote folder to search in. Default is root")
+                                    .default_value("/")
+                                    .index(1))
+                                .arg(Arg::with_name("flat")
+                                    .long("flat")
+                                    .help("Use flat list of all Disk files instead of walking folders"))
+                                .arg(Arg::with_name("keep")
+                                    .long("keep")
+                                    .value_name("AGE")
+                                    .possible_values(&["oldest", "newest"])
+                                    .help("Keep the oldest or newest copy and move the others to trash"))
+                                .arg(Arg::with_name("prefer")
+                                    .long("prefer")
+                                    .value_name("GLOB")
+                                    .help("Keep the copy with path matching glob pattern, e.g. '/photos/*', and move the others to trash"))
+                                .arg(Arg::with_name("interactive")
+                                    .short("i")
+                                    .long("interactive")
+                                    .help("Ask which copy to keep when --keep and --prefer don't tell")))
+                            .subcommand(SubCommand::with_name("backup")
+                                .about("Deduplicated backups of local directories as snapshots in a repository on the Disk")
+                                .arg(Arg::with_name("repo")
+                                    .long("repo")
+                                    .value_name("PATH")
+                                    .help("Remote repository folder")
+                                    .default_value("app:/backup"))
+                                .subcommand(SubCommand::with_name("create")
+                                    .about("Back up a local directory as a new snapshot. Creates the repository if there is none")
+                                    .arg(Arg::with_name("path")
+                                        .help("Local directory")
+                                        .required(true)
+                                        .index(1))
+                                    .arg(Arg::with_name("encrypt")
+                                        .long("encrypt")
+                                        .help("Encrypt chunks and snapshots of a new repository. Existing repositories keep their setting")))
+                                .subcommand(SubCommand::with_name("list")
+                                    .about("List snapshots"))
+                                .subcommand(SubCommand::with_name("restore")
+                                    .about("Restore files of a snapshot")
+                                    .arg(Arg::with_name("snapshot")
+                                        .help("Snapshot id, see backup list")
+                                        .required(true)
+                                        .index(1))
+                                    .arg(Arg::with_name("target")
+                                        .help("Local directory to restore into")
+                                        .required(true)
+                                        .index(2)))
+                                .subcommand(SubCommand::with_name("forget")
+                                    .about("Delete snapshots. Their chunks stay until prune")
+                                    .arg(Arg::with_name("snapshot")
+                                        .help("Snapshot ids")
+                                        .multiple(true)
+                                        .required_unless("keep_last")
+                                        .index(1))
+                                    .arg(Arg::with_name("keep_last")
+                                        .long("keep-last")
+                                        .value_name("N")
+                                        .help("Delete all but N latest snapshots")))
+                                .subcommand(SubCommand::with_name("prune")
+                                    .about("Delete chunks no snapshot refers to"))
+                                .subcommand(SubCommand::with_name("check")
+                                    .about("Check that chunks of every snapshot are stored")
+                                    .arg(Arg::with_name("read_data")
+                                        .long("read-data")
+                                        .help("Download every chunk and check its content"))))
+                            .subcommand(SubCommand::with_name("snapshot")
+                                .about("Upload local directory into a new timestamped folder, copying unchanged files from the previous one on the server")
+                                .arg(Arg::with_name("path")
+                                    .help("Local directory")
+                                    .required(true)
+                                    .index(1))
+                                .arg(Arg::with_name("remote")
+                                    .help("Remote folder snapshots are kept in")
+                                    .required(true)
+                                    .index(2))
+                                .arg(Arg::with_name("keep_daily")
+                                    .long("keep-daily")
+                                    .value_name("N")
+                                    .help("Keep the latest snapshot of each of N last days"))
+                                .arg(Arg::with_name("keep_weekly")
+                                    .long("keep-weekly")
+                                    .value_name("N")
+                                    .help("Keep the latest snapshot of each of N last weeks"))
+                                .arg(Arg::with_name("keep_monthly")
+                                    .long("keep-monthly")
+                                    .value_name("N")
+                                    .help("Keep the latest snapshot of each of N last months"))
+                                .arg(Arg::with_name("permanently")
+                                    .long("permanently")
+                                    .help("Delete old snapshots for good instead of moving to trash"))
+                                .arg(Arg::with_name("force")
+                                    .long("force")
+                                    .help("Skip free space check")))
+                            .subcommand(SubCommand::with_name("shell")
+                                .about("Interactive shell with remote and local current directories, history and completion of remote paths"))
+                            .subcommand(SubCommand::with_name("tui")
+                                .about("Two-pane terminal file manager: local directory on the left, the Disk on the right"))
                             .subcommand(SubCommand::with_name("publish")
-                                .about("(not implemented) Publish directory and get link to STDOUT"))
+                                .about("Publish file or directory and get link to STDOUT")
+                                .arg(Arg::with_name("path")
+                                    .help("Remote path")
+                                    .required(true)
+                                    .index(1)))
                             .subcommand(SubCommand::with_name("unpublish")
-                                .about("(not implemented) Unpublish directory"))
+                                .about("Unpublish file or directory")
+                                .arg(Arg::with_name("path")
+                                    .help("Remote path")
+                                    .required(true)
+                                    .index(1)))
                             .subcommand(SubCommand::with_name("token")
                                 .about("Get OAuth token proccedure. You will get URL to Yandex OAuth page")
                                 .arg(Arg::with_name("newtoken")
@@ -93,6 +565,74 @@ pub fn init_cli() -> clap::ArgMatches<'static>{
                                     .takes_value(true)
                                     .required(true)
                                     .index(1)))
-                                .get_matches()
+                            .subcommand(SubCommand::with_name("completions")
+                                .about("Write shell completion script to STDOUT")
+                                .arg(Arg::with_name("shell")
+                                    .possible_values(&["bash", "zsh", "fish", "powershell"])
+                                    .required(true)
+                                    .index(1)))
+}
+
+/// Completion script generated by clap, plus completion of remote paths starting with '/'
+/// through `__complete` where the shell allows extending it
+pub fn completions<W: Write>(shell: &str, out: &mut W) -> std::io::Result<()> {
+    let commands = REMOTE_PATH_COMMANDS.join(" ");
+    let value_options = GLOBAL_VALUE_OPTIONS.join("|");
+    match shell {
+        "bash" => {
+            visible_cli().gen_completions_to(BIN_NAME, Shell::Bash, out);
+            write!(out, r#"
+_yadisk_client_remote() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" cmd="" i
+    for (( i=1; i < COMP_CWORD; i++ )); do
+        case "${{COMP_WORDS[i]}}" in
+            {value_options}) (( i++ )) ;;
+            -*) ;;
+            *) cmd="${{COMP_WORDS[i]}}"; break ;;
+        esac
+    done
+    if [[ -n "$cmd" && " {commands} " == *" $cmd "* && "$cur" == /* ]]; then
+        COMPREPLY=( $({bin} __complete "$cur" 2>/dev/null) )
+        compopt -o nospace
+        return 0
+    fi
+    _{bin} "$@"
+}}
+complete -F _yadisk_client_remote -o bashdefault -o default {bin}
+"#, commands = commands, value_options = value_options, bin = BIN_NAME)
+        }
+        "zsh" => {
+            visible_cli().gen_completions_to(BIN_NAME, Shell::Zsh, out);
+            write!(out, r#"
+_yadisk_client_remote() {{
+    local cmd="" i
+    for (( i=2; i < CURRENT; i++ )); do
+        case "${{words[i]}}" in
+            {value_options}) (( i++ )) ;;
+            -*) ;;
+            *) cmd="${{words[i]}}"; break ;;
+        esac
+    done
+    if [[ -n "$cmd" && " {commands} " == *" $cmd "* && "$PREFIX" == /* ]]; then
+        local -a paths
+        paths=(${{(f)"$({bin} __complete "$PREFIX" 2>/dev/null)"}})
+        compadd -S '' -- $paths
+    else
+        _{bin} "$@"
+    fi
+}}
+compdef _yadisk_client_remote {bin}
+"#, commands = commands, value_options = value_options, bin = BIN_NAME)
+        }
+        "fish" => {
+            visible_cli().gen_completions_to(BIN_NAME, Shell::Fish, out);
+            writeln!(out, "complete -c {bin} -n '__fish_seen_subcommand_from {commands}; and string match -q \"/*\" -- (commandline -ct)' \
+    -f -a '({bin} __complete (commandline -ct) 2>/dev/null)'", commands = commands, bin = BIN_NAME)
+        }
+        _ => {
+            visible_cli().gen_completions_to(BIN_NAME, Shell::PowerShell, out);
+            Ok(())
+        }
+    }
 }
 
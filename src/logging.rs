@@ -0,0 +1,64 @@
+use colored::*;
+use log::{Level, LevelFilter};
+
+//
+// Logging setup
+//
+// Diagnostics go to STDERR (and optionally to a log file), so STDOUT
+// stays reserved for command output.
+//
+
+fn level_from_matches(matches: &clap::ArgMatches) -> LevelFilter {
+    if matches.is_present("quiet") {
+        return LevelFilter::Error;
+    }
+    match matches.occurrences_of("verbose") {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+fn colored_level(level: Level) -> ColoredString {
+    match level {
+        Level::Error => "error".red(),
+        Level::Warn => "warning".yellow(),
+        Level::Info => "info".green(),
+        Level::Debug => "debug".blue(),
+        Level::Trace => "trace".bright_black(),
+    }
+}
+
+pub fn init_logging(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let level = level_from_matches(matches);
+
+    // Dependencies (reqwest, hyper, ...) are only interesting when tracing
+    let deps_level = if level == LevelFilter::Trace { LevelFilter::Debug } else { LevelFilter::Warn };
+
+    let stderr = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!("[{}] {}", colored_level(record.level()), message))
+        })
+        .chain(std::io::stderr());
+
+    let mut dispatch = fern::Dispatch::new()
+        .level(deps_level)
+        .level_for("yadisk_client", level)
+        .chain(stderr);
+
+    if let Some(log_file) = matches.value_of("log_file") {
+        let file = fern::Dispatch::new()
+            .format(|out, message, record| {
+                out.finish(format_args!("{} {:5} [{}] {}",
+                                        chrono::Local::now().to_rfc3339(),
+                                        record.level(),
+                                        record.target(),
+                                        message))
+            })
+            .chain(fern::log_file(log_file)?);
+        dispatch = dispatch.chain(file);
+    }
+
+    dispatch.apply()?;
+    Ok(())
+}
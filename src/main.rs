@@ -3,20 +3,27 @@ extern crate mime;
 extern crate serde_json;
 extern crate colored;
 #[macro_use] extern crate text_io;
+#[macro_use] extern crate log;
 
 use std::fs::File;
 use std::fs;
 use std::io::prelude::*;
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use notify::event::EventKind::*;
 use notify::{Watcher, RecommendedWatcher, RecursiveMode};
 
 mod cli;
+mod logging;
 mod yandex_disk_api;
 use yandex_disk_api::*;
+use yandex_disk_api::yandex_disk_data_structures::{MediaType, ResourceType};
+use yandex_disk_api::yandex_disk_query::parse_fields;
 
 const BASE_API_URL: &str = "https://cloud-api.yandex.net:443/v1/disk";
 
+// Exit codes of `info` thresholds, 1 is taken by errors
+const EXIT_QUOTA_WARNING: i32 = 3;
+const EXIT_QUOTA_CRITICAL: i32 = 4;
+
 fn trim_newline(s: &mut String) {
     if s.ends_with('\n') {
         s.pop();
@@ -27,17 +34,17 @@ fn trim_newline(s: &mut String) {
 }
 
 fn start_watch(path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Starting watch for: {}", path);
+    info!("Starting watch for: {}", path);
 
     let (tx, rx) = std::sync::mpsc::channel();
 
     // Automatically select the best implementation for your platform.
     // You can also access each implementation directly e.g. INotifyWatcher.
-    let mut watcher: RecommendedWatcher = Watcher::new_immediate(move |res| tx.send(res).unwrap())?;
+    let mut watcher: RecommendedWatcher = notify::recommended_watcher(move |res| tx.send(res).unwrap())?;
 
     // Add a path to be watched. All files and directories at that path and
     // below will be monitored for changes.
-    watcher.watch(path, RecursiveMode::Recursive)?;
+    watcher.watch(std::path::Path::new(path), RecursiveMode::Recursive)?;
 
     for res in rx {
         match res {
@@ -52,11 +59,11 @@ fn start_watch(path: &str) -> Result<(), Box<dyn std::error::Error>> {
                     println!("Remove event! {:#?}", event.paths);
                 },
                 _ => {
-                    println!("Other event! {:#?}", event);
+                    debug!("Other event! {:#?}", event);
                 }
             },
             Err(event) => {
-                println!("watch error: {:?}", event)
+                error!("watch error: {:?}", event)
             }
         }
     };
@@ -64,10 +71,119 @@ fn start_watch(path: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// "90%" or "90"
+fn parse_percent(value: Option<&str>) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    match value {
+        Some(v) => Ok(Some(v.trim().trim_end_matches('%').parse::<f64>()
+                            .map_err(|_| format!("Bad percentage: {}", v))?)),
+        None => Ok(None),
+    }
+}
+
+fn find_filter(matches: &clap::ArgMatches) -> Result<yandex_disk_find::FindFilter, Box<dyn std::error::Error>> {
+    let date = |name: &str| matches.value_of(name).map(yandex_disk_find::parse_date).transpose();
+    let size = |name: &str| matches.value_of(name).map(parse_size).transpose();
+    if matches.is_present("flat") && matches.value_of("type") == Some("dir") {
+        return Err("--flat finds files only, it can't be used with --type dir".into());
+    }
+    Ok(yandex_disk_find::FindFilter {
+        name: matches.value_of("name").map(glob::Pattern::new).transpose()?,
+        regex: matches.value_of("regex").map(regex::Regex::new).transpose()?,
+        resource_type: matches.value_of("type").map(|x| if x == "dir" { ResourceType::Dir } else { ResourceType::File }),
+        min_size: size("min_size")?,
+        max_size: size("max_size")?,
+        created_after: date("created_after")?,
+        created_before: date("created_before")?,
+        modified_after: date("modified_after")?,
+        modified_before: date("modified_before")?,
+        mime_type: matches.value_of("mime_type").map(glob::Pattern::new).transpose()?,
+        media_type: matches.value_of("media_type").map(str::parse::<MediaType>).transpose()?,
+        md5: matches.value_of("md5").map(String::from),
+    })
+}
+
+fn backup(url: &str, settings: &config::Config, matches: &clap::ArgMatches, defaults: &TransferOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let repo = matches.value_of("repo").unwrap();
+    match matches.subcommand() {
+        ("create", Some(create_matches)) => {
+            let options = TransferOptions { encrypt: create_matches.is_present("encrypt"), ..defaults.clone() };
+            yandex_disk_backup::create_snapshot(url, settings, repo, create_matches.value_of("path").unwrap(), &options)?;
+            Ok(())
+        },
+        ("list", _) => yandex_disk_backup::list_snapshots(url, settings, repo, defaults),
+        ("restore", Some(restore_matches)) => {
+            yandex_disk_backup::restore_snapshot(url, settings, repo,
+                                                 restore_matches.value_of("snapshot").unwrap(),
+                                                 restore_matches.value_of("target").unwrap(),
+                                                 defaults)
+        },
+        ("forget", Some(forget_matches)) => {
+            let ids: Vec<&str> = forget_matches.values_of("snapshot").map(|x| x.collect()).unwrap_or_default();
+            let keep_last = forget_matches.value_of("keep_last").map(str::parse::<usize>).transpose()?;
+            yandex_disk_backup::forget_snapshots(url, settings, repo, &ids, keep_last, defaults)
+        },
+        ("prune", _) => yandex_disk_backup::prune(url, settings, repo, defaults),
+        ("check", Some(check_matches)) => {
+            yandex_disk_backup::check(url, settings, repo, check_matches.is_present("read_data"), defaults)
+        },
+        _ => Err("No backup command given, see backup --help".into()),
+    }
+}
+
+/// Key file from command line or config, passphrase from environment
+fn encryption_key(matches: &clap::ArgMatches, settings: &config::Config) -> Result<Option<yandex_disk_crypt::Key>, Box<dyn std::error::Error>> {
+    if let Some(path) = matches.value_of("key_file").map(String::from).or_else(|| settings.get_str("key_file").ok()) {
+        return Ok(Some(yandex_disk_crypt::Key::from_file(path.as_str()).map_err(|e| format!("{}: {}", path, e))?));
+    }
+    match std::env::var(yandex_disk_crypt::PASSPHRASE_ENV) {
+        Ok(passphrase) => Ok(Some(yandex_disk_crypt::Key::from_passphrase(passphrase.as_str())?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Transfer options shared by all commands, from config and environment
+fn transfer_defaults(matches: &clap::ArgMatches, settings: &config::Config) -> Result<TransferOptions, Box<dyn std::error::Error>> {
+    let mut defaults = TransferOptions { key: encryption_key(matches, settings)?, ..Default::default() };
+    if let Ok(exclude) = settings.get_array("compress_exclude") {
+        defaults.compress_exclude = exclude.into_iter().map(|x| x.into_str()).collect::<Result<_, _>>()?;
+    }
+    Ok(defaults)
+}
+
+fn transfer_options(matches: &clap::ArgMatches, defaults: &TransferOptions) -> Result<TransferOptions, Box<dyn std::error::Error>> {
+    Ok(TransferOptions {
+        overwrite: matches.value_of("overwrite").unwrap_or_default().eq_ignore_ascii_case("true"),
+        check_quota: !matches.is_present("force"),
+        verify: !matches.is_present("no_verify"),
+        retries: matches.value_of("retries").unwrap_or("0").parse::<u32>()?,
+        split: matches.is_present("split"),
+        split_size: matches.value_of("split_size").map(parse_size).transpose()?,
+        parallel: matches.value_of("parallel").unwrap_or("4").parse::<usize>()?,
+        encrypt: matches.is_present("encrypt"),
+        encrypt_names: matches.is_present("encrypt_names"),
+        compress: matches.value_of("compress").map(|x| x.parse()).transpose()?,
+        ..defaults.clone()
+    })
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let matches = cli::init_cli();
 
+    logging::init_logging(&matches)?;
+
+    // Needs no account
+    if let ("completions", Some(completions_matches)) = matches.subcommand() {
+        cli::completions(completions_matches.value_of("shell").unwrap(), &mut std::io::stdout())?;
+        return Ok(());
+    }
+    if let ("keygen", Some(keygen_matches)) = matches.subcommand() {
+        let path = keygen_matches.value_of("path").unwrap();
+        yandex_disk_crypt::generate_key_file(path).map_err(|e| format!("{}: {}", path, e))?;
+        info!("Key written to {}, keep a copy in a safe place: encrypted files can't be restored without it", path);
+        return Ok(());
+    }
+
     let mut oauth_token = String::new();
 
     //
@@ -85,7 +201,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 f.read_to_string(&mut oauth_token).expect("Error reading value");
                 trim_newline(&mut oauth_token);
             }
-            Err(_) => println!("Error reading file"),
+            Err(e) => error!("Error reading file {}: {}", c, e),
         }
     } 
 
@@ -105,54 +221,181 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // FIXME some magic number for fast check
     // Convenient conf check should be implemented
-    if settings.get_str("oauth_token")?.len() < 5 { 
+    // Transfers take tokens of profiles
+    if matches.subcommand_name() != Some("transfer") && settings.get_str("oauth_token")?.len() < 5 { 
         return Err(String::from("No configuration provided").into());
     }
 
-    println!("OAuth token: {}", settings.get_str("oauth_token")?);
-
     settings.set("url", matches.value_of("url").unwrap_or(BASE_API_URL))?;
+    if matches.is_present("no_cache") {
+        settings.set("metadata_cache", false)?;
+    }
+    yandex_disk_cache::set_offline(matches.is_present("offline"));
+    yandex_disk_dry_run::set_dry_run(matches.is_present("dry_run"));
+    let defaults = transfer_defaults(&matches, &settings)?;
         
     match matches.subcommand() {
-        ("list", _) => { 
-            let path = utf8_percent_encode(matches.subcommand_matches("list")
-                                                  .unwrap()
-                                                  .value_of("path")
-                                                  .unwrap_or_default(), NON_ALPHANUMERIC
-                                          ).to_string();
-            get_list(settings.get_str("url")?.as_str(), &settings, &path)
+        ("list", Some(list_matches)) => { 
+            let fields = list_matches.value_of("fields").map(parse_fields);
+            get_list(settings.get_str("url")?.as_str(), &settings,
+                     list_matches.value_of("path").unwrap_or_default(),
+                     fields.as_deref())
+        },
+        ("last", Some(last_matches)) => {
+            let fields = last_matches.value_of("fields").map(parse_fields);
+            get_last(settings.get_str("url")?.as_str(), &settings,
+                     last_matches.value_of("limit").unwrap().parse::<u64>()?,
+                     fields.as_deref())
         },
-        ("last", _) => { get_last(settings.get_str("url")?.as_str(), &settings, matches.subcommand_matches("last").unwrap().value_of("limit").unwrap().to_string().parse::<u64>().unwrap()) },
-        ("info", _) => { get_info(&settings) },
-        ("download", _) => {
-            let path = matches.subcommand_matches("download")
-                                                  .unwrap()
-                                                  .value_of("path")
-                                                  .unwrap_or_default();
-            let target_path = matches.subcommand_matches("download")
-                                                  .unwrap()
-                                                  .value_of("target")
-                                                  .unwrap_or_default();
-            download_file(settings.get_str("url")?.as_str(), &settings, &path, Some(&target_path))
-         },
-         ("upload", _) => {
-            let path = matches.subcommand_matches("upload")
-                                                  .unwrap()
-                                                  .value_of("path")
-                                                  .unwrap_or_default();
-            let remote_path = matches.subcommand_matches("upload")
-                                                  .unwrap()
-                                                  .value_of("remote")
-                                                  .unwrap_or_default();
-            let overwrite_str = matches.subcommand_matches("upload")
-                                                  .unwrap()
-                                                  .value_of("overwrite")
-                                                  .unwrap_or_default();
-            let mut overwrite = false;
-            if overwrite_str.eq_ignore_ascii_case("true") {
-                overwrite = true;
+        ("info", Some(info_matches)) => {
+            let status = get_info(&settings,
+                                  parse_percent(info_matches.value_of("warn_at"))?,
+                                  parse_percent(info_matches.value_of("crit_at"))?)?;
+            match status {
+                QuotaStatus::Critical => std::process::exit(EXIT_QUOTA_CRITICAL),
+                QuotaStatus::Warning => std::process::exit(EXIT_QUOTA_WARNING),
+                QuotaStatus::Ok => Ok(()),
+            }
+        },
+        ("download", Some(download_matches)) => {
+            let path = download_matches.value_of("path")
+                                       .unwrap_or_default();
+            let target_path = download_matches.value_of("target");
+            download_file(settings.get_str("url")?.as_str(), &settings, path, target_path, &transfer_options(download_matches, &defaults)?)
+         },
+         ("cat", Some(cat_matches)) => {
+            cat_file(settings.get_str("url")?.as_str(), &settings, cat_matches.value_of("path").unwrap(), &transfer_options(cat_matches, &defaults)?)
+         },
+         ("upload", Some(upload_matches)) if upload_matches.is_present("from_url") || upload_matches.is_present("from_url_list") => {
+            // There is no local file here, so the single positional argument is the remote one
+            let remote_path = upload_matches.value_of("remote")
+                                            .or_else(|| upload_matches.value_of("path"))
+                                            .unwrap_or_default();
+            match upload_matches.value_of("from_url") {
+                Some(source_url) => upload_from_url(settings.get_str("url")?.as_str(), &settings, source_url, remote_path),
+                None => upload_from_url_list(settings.get_str("url")?.as_str(), &settings,
+                                             upload_matches.value_of("from_url_list").unwrap(), remote_path),
+            }
+         },
+         ("upload", Some(upload_matches)) => {
+            let path = upload_matches.value_of("path")
+                                     .unwrap_or_default();
+            let remote_path = upload_matches.value_of("remote")
+                                            .unwrap_or_default();
+            let mut options = transfer_options(upload_matches, &defaults)?;
+            if upload_matches.is_present("dedupe") {
+                options.dedupe = Some(yandex_disk_dedupe::ContentIndex::fetch(settings.get_str("url")?.as_str(), &settings)?.shared());
+            }
+            if upload_matches.is_present("recursive") {
+                upload_dir(settings.get_str("url")?.as_str(), &settings, path, remote_path, &options)
+            } else {
+                upload_file(settings.get_str("url")?.as_str(), &settings, path, remote_path, &options)
+            }
+         },
+         ("du", Some(du_matches)) => {
+            let max_depth = du_matches.value_of("max_depth").map(str::parse::<usize>).transpose()?;
+            yandex_disk_usage::disk_usage(settings.get_str("url")?.as_str(), &settings,
+                                          du_matches.value_of("path").unwrap(),
+                                          max_depth,
+                                          du_matches.value_of("sort").unwrap().parse()?,
+                                          du_matches.is_present("bytes"),
+                                          du_matches.is_present("flat"))
+         },
+         ("tree", Some(tree_matches)) => {
+            let max_depth = tree_matches.value_of("max_depth").map(str::parse::<usize>).transpose()?;
+            yandex_disk_usage::tree(settings.get_str("url")?.as_str(), &settings,
+                                    tree_matches.value_of("path").unwrap(),
+                                    max_depth,
+                                    tree_matches.is_present("size"),
+                                    tree_matches.is_present("flat"))
+         },
+         ("find", Some(find_matches)) => {
+            let action = if find_matches.is_present("delete") {
+                yandex_disk_find::FindAction::Delete { permanently: find_matches.is_present("permanently") }
+            } else if let Some(dir) = find_matches.value_of("download_to") {
+                yandex_disk_find::FindAction::Download { target_dir: dir.to_string(), options: transfer_options(find_matches, &defaults)? }
+            } else if let Some(dir) = find_matches.value_of("move_to") {
+                yandex_disk_find::FindAction::Move { target_dir: dir.to_string() }
+            } else {
+                yandex_disk_find::FindAction::Print
+            };
+            yandex_disk_find::find(settings.get_str("url")?.as_str(), &settings,
+                                   find_matches.value_of("path").unwrap(),
+                                   &find_filter(find_matches)?,
+                                   find_matches.is_present("flat"),
+                                   find_matches.value_of("format").unwrap().parse()?,
+                                   find_matches.value_of("fields").map(parse_fields).as_deref(),
+                                   &action)
+         },
+         ("dupes", Some(dupes_matches)) => {
+            let rule = yandex_disk_dupes::KeepRule {
+                prefer: dupes_matches.value_of("prefer").map(glob::Pattern::new).transpose()?,
+                oldest: dupes_matches.value_of("keep").map(|x| x == "oldest"),
+                interactive: dupes_matches.is_present("interactive"),
+            };
+            yandex_disk_dupes::dupes(settings.get_str("url")?.as_str(), &settings,
+                                     dupes_matches.value_of("path").unwrap(),
+                                     dupes_matches.is_present("flat"),
+                                     &rule)
+         },
+         ("backup", Some(backup_matches)) => {
+            backup(settings.get_str("url")?.as_str(), &settings, backup_matches, &defaults)
+         },
+         ("snapshot", Some(snapshot_matches)) => {
+            let keep = |name: &str| snapshot_matches.value_of(name).map_or(Ok(0), str::parse::<usize>);
+            let retention = yandex_disk_snapshot::Retention {
+                daily: keep("keep_daily")?,
+                weekly: keep("keep_weekly")?,
+                monthly: keep("keep_monthly")?,
+            };
+            yandex_disk_snapshot::snapshot(settings.get_str("url")?.as_str(), &settings,
+                                           snapshot_matches.value_of("path").unwrap(),
+                                           snapshot_matches.value_of("remote").unwrap(),
+                                           &retention,
+                                           snapshot_matches.is_present("permanently"),
+                                           &TransferOptions { check_quota: !snapshot_matches.is_present("force"), ..defaults.clone() })
+         },
+         ("shell", _) => {
+            yandex_disk_shell::run(settings.get_str("url")?.as_str(), &settings, &defaults)
+         },
+         ("tui", _) => {
+            yandex_disk_tui::run(settings.get_str("url")?.as_str(), &settings, &defaults)
+         },
+         ("publish", Some(publish_matches)) => {
+            println!("{}", publish_resource(settings.get_str("url")?.as_str(), &settings, publish_matches.value_of("path").unwrap())?);
+            Ok(())
+         },
+         ("unpublish", Some(unpublish_matches)) => {
+            unpublish_resource(settings.get_str("url")?.as_str(), &settings, unpublish_matches.value_of("path").unwrap())
+         },
+         ("__complete", Some(complete_matches)) => {
+            // Completion scripts show whatever is printed, errors only go to the log
+            match yandex_disk_complete::complete(settings.get_str("url")?.as_str(), &settings, complete_matches.value_of("word").unwrap()) {
+                Ok(paths) => paths.iter().for_each(|x| println!("{}", x)),
+                Err(e) => debug!("No completions: {}", e),
             }
-            upload_file(settings.get_str("url")?.as_str(), &settings, &path, &remote_path, overwrite)
+            Ok(())
+         },
+         ("batch", Some(batch_matches)) => {
+            yandex_disk_batch::batch(settings.get_str("url")?.as_str(), &settings,
+                                     batch_matches.value_of("file").unwrap(),
+                                     batch_matches.value_of("results"),
+                                     batch_matches.value_of("jobs").unwrap().parse::<usize>()?,
+                                     batch_matches.is_present("stop_on_error"),
+                                     &transfer_options(batch_matches, &defaults)?)
+         },
+         ("transfer", Some(transfer_matches)) => {
+            let from = yandex_disk_transfer::Location::parse(&settings, transfer_matches.value_of("from_profile").unwrap())?;
+            let to = yandex_disk_transfer::Location::parse(&settings, transfer_matches.value_of("to_profile").unwrap())?;
+            yandex_disk_transfer::transfer(&from, &to,
+                                           transfer_matches.is_present("recursive"),
+                                           transfer_matches.value_of("method").unwrap().parse()?,
+                                           &transfer_options(transfer_matches, &defaults)?)
+         },
+         ("verify", Some(verify_matches)) => {
+            yandex_disk_verify::verify(settings.get_str("url")?.as_str(), &settings,
+                                       verify_matches.value_of("local").unwrap(),
+                                       verify_matches.value_of("remote").unwrap())
          },
          ("delete", _) => {
             let remote_path = matches.subcommand_matches("delete")
@@ -161,7 +404,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                                     .value_of("remote")
                                                     .unwrap_or_default();
             let permanently_flag = true;
-            delete_remote_file(settings.get_str("url")?.as_str(), oauth_token.as_str(), remote_path, permanently_flag)
+            delete_remote_file(settings.get_str("url")?.as_str(), &settings, remote_path, permanently_flag)
          },
          ("login", _) => {
             let ti: yandex_disk_oauth::TokenInfo = yandex_disk_oauth::cli_auth_procedure(&settings)?;
@@ -169,7 +412,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Ok(())
          }
          ("watch", _) => {
-            println!("There will be watch!");
             let path = matches.subcommand_matches("watch")
                             .unwrap()
                             .value_of("path")
@@ -177,7 +419,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             start_watch(path)?;
             Ok(())
          }
-        _ => {println!("No known command given. Use help please."); Ok (())}
+        _ => {error!("No known command given. Use help please."); Ok (())}
     }
 
 
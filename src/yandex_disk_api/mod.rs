@@ -1,6 +1,7 @@
 use colored::*;
 
 use std::str::FromStr;
+use std::time::Instant;
 use mime::Mime;
 
 use url::{Url};
@@ -10,66 +11,291 @@ use std::io;
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 
 use std::fs::File;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-mod yandex_disk_data_structures;
+pub mod yandex_disk_data_structures;
 use yandex_disk_data_structures::*;
 
 pub mod yandex_disk_oauth;
+pub mod yandex_disk_query;
+use yandex_disk_query::*;
+pub mod yandex_disk_streams;
+use yandex_disk_streams::*;
+pub mod yandex_disk_verify;
+pub mod yandex_disk_usage;
+pub mod yandex_disk_find;
+pub mod yandex_disk_split;
+pub mod yandex_disk_crypt;
+pub mod yandex_disk_compress;
+pub mod yandex_disk_backup;
+pub mod yandex_disk_snapshot;
+pub mod yandex_disk_dedupe;
+pub mod yandex_disk_dupes;
+pub mod yandex_disk_shell;
+pub mod yandex_disk_tui;
+pub mod yandex_disk_complete;
+pub mod yandex_disk_cache;
+pub mod yandex_disk_transfer;
+pub mod yandex_disk_batch;
+pub mod yandex_disk_dry_run;
+#[cfg(test)]
+pub(crate) mod yandex_disk_test_util;
+use yandex_disk_dry_run::is_dry_run;
+use yandex_disk_cache::cached_api_request;
+use yandex_disk_compress::Compression;
+
+const REDACTED: &str = "<redacted>";
+
+/// Replace every query string value with a placeholder. Download and upload
+/// hrefs carry signed credentials in the query, so URLs are never logged as is.
+pub fn redact_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut parsed) => {
+            if parsed.query().is_some() {
+                let keys: Vec<String> = parsed.query_pairs().map(|(k, _v)| k.into_owned()).collect();
+                parsed.query_pairs_mut()
+                    .clear()
+                    .extend_pairs(keys.iter().map(|k| (k.as_str(), REDACTED)));
+            }
+            parsed.to_string()
+        }
+        Err(_) => match url.find('?') {
+            Some(pos) => format!("{}?{}", &url[..pos], REDACTED),
+            None => url.to_string(),
+        },
+    }
+}
+
+fn redact_headers(headers: &reqwest::header::HeaderMap) -> String {
+    headers.iter()
+        .map(|(name, value)| {
+            if name == reqwest::header::AUTHORIZATION {
+                format!("{}: {}", name, REDACTED)
+            } else {
+                format!("{}: {}", name, value.to_str().unwrap_or("<binary>"))
+            }
+        })
+        .collect::<Vec<String>>().join(", ")
+}
+
+//
+// HTTP clients
+//
+// Clients are shared by all requests of the process and keep connections alive between
+// them, which matters for long sessions like `shell`.
+//
+
+static API_CLIENT: OnceLock<reqwest::blocking::Client> = OnceLock::new();
+static TRANSFER_CLIENT: OnceLock<reqwest::blocking::Client> = OnceLock::new();
+
+/// Client for API calls, with the default timeout
+pub fn api_client() -> &'static reqwest::blocking::Client {
+    API_CLIENT.get_or_init(reqwest::blocking::Client::new)
+}
+
+/// Client for data transfers, without timeout: streams from pipes may take as long as the producer needs
+pub fn transfer_client() -> &'static reqwest::blocking::Client {
+    TRANSFER_CLIENT.get_or_init(|| {
+        reqwest::blocking::Client::builder().timeout(None).build().expect("HTTP client can't be created")
+    })
+}
+
+/// Send a request, logging method, redacted URL, status and elapsed time.
+pub fn send_request(
+    client: &reqwest::blocking::Client,
+    request: reqwest::blocking::RequestBuilder,
+) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    let request = request.build()?;
+    let method = request.method().clone();
+    let url = redact_url(request.url().as_str());
+    if yandex_disk_cache::is_offline() {
+        return Err(format!("Offline, {} {} is not sent", method, url).into());
+    }
+    if yandex_disk_dry_run::refuses(&method, is_dry_run()) {
+        return Err(format!("Dry run, {} {} is not sent", method, url).into());
+    }
+    if method != reqwest::Method::GET {
+        yandex_disk_cache::invalidate();
+    }
+
+    debug!("--> {} {}", method, url);
+    trace!("    headers: {}", redact_headers(request.headers()));
+
+    let started = Instant::now();
+    match client.execute(request) {
+        Ok(resp) => {
+            debug!("<-- {} {} {} ({} ms)", method, url, resp.status(), started.elapsed().as_millis());
+            trace!("    headers: {}", redact_headers(resp.headers()));
+            Ok(resp)
+        }
+        Err(e) => {
+            debug!("<-- {} {} failed ({} ms)", method, url, started.elapsed().as_millis());
+            Err(e.into())
+        }
+    }
+}
 
 pub fn make_api_request(
     url: &str,
     conf: &config::Config
 ) -> Result<String, Box<dyn std::error::Error>> {
-    println!("Making API request: {}", url.blue());
-    println!("Token: {:?}", conf.get_str("oauth_token")?.as_str());
-    let rclient = reqwest::blocking::Client::new();
-    let resp = rclient.get(url)
-        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?.as_str()))
-        .send()?;
+    make_api_call(reqwest::Method::GET, url, conf)
+}
 
-    if resp.status() == reqwest::StatusCode::OK {
-        println!("OK");
+/// Call an API method and return the JSON body of any successful (2xx) response.
+pub fn make_api_call(
+    method: reqwest::Method,
+    url: &str,
+    conf: &config::Config
+) -> Result<String, Box<dyn std::error::Error>> {
+    make_api_call_with_body(method, url, conf, None)
+}
+
+/// API call sending a JSON body, e.g. PATCH of resource properties
+pub fn make_api_call_with_body(
+    method: reqwest::Method,
+    url: &str,
+    conf: &config::Config,
+    body: Option<&serde_json::Value>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let rclient = api_client();
+    let mut request = rclient.request(method, url)
+        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?.as_str()));
+    if let Some(body) = body {
+        request = request.json(body);
+    }
+    let resp = send_request(rclient, request)?;
+
+    if resp.status().is_success() {
         let ct = Mime::from_str(resp.headers().get(reqwest::header::CONTENT_TYPE).unwrap().to_str()?)?;
         if (ct.type_() == mime::APPLICATION) && (ct.subtype() == mime::JSON) {
             Ok(resp.text()?)
         } else {
-            println!("--Mime type is not application/json");
             Err("Mime type is not application/json".to_string().into())
         }
     } else {
-        println!("--Responce status is not OK\n{:#?}", resp.status());
-        Err("Responce status is not OK".to_string().into())
+        Err(format!("Response status is not OK: {}", resp.status()).into())
     }
 }
 
-pub fn get_info(conf: &config::Config) -> Result<(), Box<dyn std::error::Error>>{
-    let disk_object: YaDisk = serde_json::from_str(
-        make_api_request(conf.get_str("url")?.as_str(), conf)?.as_str())?;
+pub fn get_disk_info(conf: &config::Config) -> Result<YaDisk, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_str(make_api_request(conf.get_str("url")?.as_str(), conf)?.as_str())?)
+}
 
-    println!("Yandex disk info:\n{:#?}", disk_object);
+#[derive(Debug, PartialEq, PartialOrd)]
+pub enum QuotaStatus {
+    Ok,
+    Warning,
+    Critical,
+}
 
-    Ok(())
+const USAGE_BAR_WIDTH: usize = 40;
+
+fn usage_bar(used_percent: f64, status: &QuotaStatus) -> String {
+    let filled = ((used_percent / 100.0) * USAGE_BAR_WIDTH as f64).round() as usize;
+    let filled = filled.min(USAGE_BAR_WIDTH);
+    let bar = "#".repeat(filled);
+    let bar = match status {
+        QuotaStatus::Ok => bar.green(),
+        QuotaStatus::Warning => bar.yellow(),
+        QuotaStatus::Critical => bar.red(),
+    };
+    format!("[{}{}]", bar, ".".repeat(USAGE_BAR_WIDTH - filled).bright_black())
 }
 
-pub fn get_last(url: &str, conf: &config::Config, limit: u64) -> Result<(), Box<dyn std::error::Error>>{
-    let s:String = make_api_request(format!("{}/resources/last-uploaded?limit={}", url, limit).as_str(), conf)?;
+/// Print disk report. Used space is compared with `warn_at` and `crit_at` percentages.
+pub fn get_info(
+    conf: &config::Config,
+    warn_at: Option<f64>,
+    crit_at: Option<f64>,
+) -> Result<QuotaStatus, Box<dyn std::error::Error>>{
+    let disk: YaDisk = get_disk_info(conf)?;
+
+    let total = disk.total_space.unwrap_or_default();
+    let used = disk.used_space.unwrap_or_default();
+    let free = disk.free_space();
+    let used_percent = if total > 0 {
+        used as f64 * 100.0 / total as f64
+    } else {
+        100.0
+    };
+
+    let status = if crit_at.is_some_and(|x| used_percent >= x) {
+        QuotaStatus::Critical
+    } else if warn_at.is_some_and(|x| used_percent >= x) {
+        QuotaStatus::Warning
+    } else {
+        QuotaStatus::Ok
+    };
+
+    if let Some(user) = disk.user.as_ref() {
+        println!("{:16}{} ({}), {}", "User:",
+                 user.login.as_deref().unwrap_or("?").blue(),
+                 user.display_name.as_deref().unwrap_or("?"),
+                 user.country.as_deref().unwrap_or("?"));
+    }
+    println!("{:16}{} of {} used, {} free",
+             "Space:", human_size(used), human_size(total), human_size(free).bright_yellow());
+    println!("{:16}{} {:.1}%", "", usage_bar(used_percent, &status), used_percent);
+    if let Some(x) = disk.trash_size {
+        println!("{:16}{}", "Trash:", human_size(x));
+    }
+    if let Some(x) = disk.max_file_size {
+        println!("{:16}{}", "Max file size:", human_size(x));
+    }
+    if let Some(x) = disk.is_paid {
+        println!("{:16}{}", "Paid:", if x { "yes" } else { "no" });
+    }
+    if let Some(x) = disk.unlimited_autoupload_enabled {
+        println!("{:16}{}", "Autoupload:", if x { "unlimited" } else { "limited" });
+    }
+    if let Some(x) = disk.revision {
+        println!("{:16}{}", "Revision:", x);
+    }
+
+    let mut folders: Vec<(&String, &String)> = disk.system_folders.iter().collect();
+    folders.sort();
+    println!("System folders:");
+    for (name, path) in folders {
+        println!("  {:14}{}", name, path.blue());
+    }
+
+    match status {
+        QuotaStatus::Critical => warn!("Disk usage {:.1}% is above critical threshold", used_percent),
+        QuotaStatus::Warning => warn!("Disk usage {:.1}% is above warning threshold", used_percent),
+        QuotaStatus::Ok => (),
+    }
+
+    Ok(status)
+}
+
+/// Print recently uploaded files. Only `fields` of them are requested, what listing lines show by default.
+pub fn get_last(url: &str, conf: &config::Config, limit: u64, fields: Fields) -> Result<(), Box<dyn std::error::Error>>{
+    let query = ResourceQuery::last_uploaded().limit(limit).item_fields(fields.or(Some(LISTING_FIELDS)));
+    let s:String = cached_api_request(query.url(url).as_str(), conf)?;
     let rl:ResourceList = serde_json::from_str(s.as_str())?;
 
     println!("Last content:\n{}",
                  rl.items.iter()
                     .map(|x| format!(" ↳ ({}) {:30} Type: {} CTime: {} MTime: {}",
-                                     x.r#type.bright_black(),
+                                     x.r#type.to_string().bright_black(),
                                      x.name.blue(), 
-                                     x.media_type.bright_yellow(), 
-                                     x.created.bright_black(), 
-                                     x.modified.bright_black()))
+                                     x.media_type().as_str().bright_yellow(), 
+                                     x.created.to_rfc3339().bright_black(), 
+                                     x.modified.to_rfc3339().bright_black()))
                     .collect::<Vec<String>>().join("\n"));
                 
     Ok(())
 }
 
-pub fn get_list(url: &str, conf: &config::Config, path: &str) -> Result<(), Box<dyn std::error::Error>>{
-    let s:String = make_api_request(format!("{}/resources?path={}", url, path).as_str(), conf)?;
+/// Print resource and directory content. Only `fields` of them are requested, what is shown by default.
+pub fn get_list(url: &str, conf: &config::Config, path: &str, fields: Fields) -> Result<(), Box<dyn std::error::Error>>{
+    let query = ResourceQuery::resource(path)
+        .fields(fields.or(Some(&["file", "size"])))
+        .item_fields(fields.or(Some(LISTING_FIELDS)));
+    let s:String = cached_api_request(query.url(url).as_str(), conf)?;
     let r:Resource = serde_json::from_str(s.as_str())?;
 
     println!("Name: {}\n\
@@ -78,32 +304,599 @@ pub fn get_list(url: &str, conf: &config::Config, path: &str) -> Result<(), Box<
               Size: {}", 
               r.name,
               r.path,
-              r.file,
-              r.size );
+              r.file.as_deref().unwrap_or_default(),
+              r.file_size() );
 
-    if r.r#type == "dir" { 
+    if r.is_dir() { 
         println!("Directory content:\n{}",
-                 r._embedded.items.iter()
+                 r.items().iter()
                   .map(|x| format!(" ↳ ({}) {:30} Type: {} CTime: {} MTime: {}",
-                                   x.r#type.bright_black(),
+                                   x.r#type.to_string().bright_black(),
                                    x.name.blue(), 
-                                   x.media_type.bright_yellow(), 
-                                   x.created.bright_black(), 
-                                   x.modified.bright_black()))
+                                   x.media_type().as_str().bright_yellow(), 
+                                   x.created.to_rfc3339().bright_black(), 
+                                   x.modified.to_rfc3339().bright_black()))
                   .collect::<Vec<String>>().join("\n"));
     }
     Ok(())
 }
 
+//
+// Resources
+//
+
+const LIST_PAGE_LIMIT: u64 = 100;
+const FILES_PAGE_LIMIT: u64 = 1000;
+
+pub fn get_resource(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    fields: Fields,
+) -> Result<Resource, Box<dyn std::error::Error>> {
+    let s: String = make_api_request(ResourceQuery::resource(path).limit(0).fields(fields).url(url).as_str(), conf)?;
+    Ok(serde_json::from_str(s.as_str())?)
+}
+
+/// Items of a paged listing, `page` fetches the items at an offset and the total if it's known.
+/// Offsets count skipped items too, the listing ends at the total or at a short page.
+fn collect_pages<F>(limit: u64, mut page: F) -> Result<Vec<Resource>, Box<dyn std::error::Error>>
+where
+    F: FnMut(usize) -> Result<(Items<Resource>, Option<u64>), Box<dyn std::error::Error>>,
+{
+    let mut items: Vec<Resource> = Vec::new();
+    let mut offset = 0;
+    loop {
+        let (list, total) = page(offset)?;
+        let listed = list.listed;
+        offset += listed;
+        items.extend(list);
+        if (listed as u64) < limit || total.is_some_and(|x| offset as u64 >= x) {
+            return Ok(items);
+        }
+    }
+}
+
+/// Every item of a remote directory with `fields` of it, fetched page by page.
+pub fn list_dir(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    fields: Fields,
+) -> Result<Vec<Resource>, Box<dyn std::error::Error>> {
+    collect_pages(LIST_PAGE_LIMIT, |offset| {
+        let query = ResourceQuery::resource(path).limit(LIST_PAGE_LIMIT).offset(offset).item_fields(fields);
+        let s: String = cached_api_request(query.url(url).as_str(), conf)?;
+        let r: Resource = serde_json::from_str(s.as_str())?;
+        let list = r._embedded.unwrap_or_default();
+        Ok((list.items, list.total))
+    })
+}
+
+/// Every file below a remote directory keyed by its path relative to `path` ('/' separated).
+pub fn walk_remote(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    fields: Fields,
+) -> Result<BTreeMap<String, Resource>, Box<dyn std::error::Error>> {
+    let mut files = walk_remote_entries(url, conf, path, None, fields)?;
+    files.retain(|_rel, item| !item.is_dir());
+    Ok(files)
+}
+
+/// Files and directories below a remote directory keyed by path relative to `path`,
+/// directories deeper than `max_depth` (1 is `path` itself) aren't listed.
+pub fn walk_remote_entries(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    max_depth: Option<usize>,
+    fields: Fields,
+) -> Result<BTreeMap<String, Resource>, Box<dyn std::error::Error>> {
+    let mut entries = BTreeMap::new();
+    let mut dirs = vec![(path.trim_end_matches('/').to_string(), String::new(), 1)];
+    while let Some((dir, rel_dir, depth)) = dirs.pop() {
+        for item in list_dir(url, conf, if dir.is_empty() { "/" } else { dir.as_str() }, fields)? {
+            let rel = if rel_dir.is_empty() { item.name.clone() } else { format!("{}/{}", rel_dir, item.name) };
+            if item.is_dir() && max_depth.is_none_or(|x| depth < x) {
+                dirs.push((format!("{}/{}", dir, item.name), rel.clone(), depth + 1));
+            }
+            entries.insert(rel, item);
+        }
+    }
+    Ok(entries)
+}
+
+/// Every file of the Disk from the flat `/resources/files` listing, optionally only of given media types.
+/// One long paginated listing is much faster than walking big trees folder by folder.
+pub fn list_files(
+    url: &str,
+    conf: &config::Config,
+    media_type: Option<MediaType>,
+    fields: Fields,
+) -> Result<Vec<Resource>, Box<dyn std::error::Error>> {
+    let mut count = 0;
+    collect_pages(FILES_PAGE_LIMIT, |offset| {
+        let query = ResourceQuery::files()
+            .limit(FILES_PAGE_LIMIT)
+            .offset(offset)
+            .media_type(media_type)
+            .item_fields(fields);
+        let s: String = cached_api_request(query.url(url).as_str(), conf)?;
+        let page: FilesResourceList = serde_json::from_str(s.as_str())?;
+        count += page.items.len();
+        debug!("{} files listed", count);
+        Ok((page.items, None))
+    })
+}
+
+/// "/a", "a", "disk:/a" are all "disk:/a"
+pub fn disk_path(path: &str) -> String {
+    if path.starts_with("disk:") {
+        path.to_string()
+    } else {
+        format!("disk:/{}", path.trim_start_matches('/'))
+    }
+}
+
+/// Files below a remote directory keyed by relative path like `walk_remote`, taken from the flat listing.
+pub fn walk_remote_flat(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    media_type: Option<MediaType>,
+    fields: Fields,
+) -> Result<BTreeMap<String, Resource>, Box<dyn std::error::Error>> {
+    let prefix = format!("{}/", disk_path(path).trim_end_matches('/'));
+    Ok(list_files(url, conf, media_type, fields)?
+        .into_iter()
+        .filter_map(|item| {
+            let rel = item.path.strip_prefix(prefix.as_str())?.to_string();
+            Some((rel, item))
+        })
+        .collect())
+}
+
+/// Every file below a local directory keyed by its path relative to `base` ('/' separated).
+pub fn walk_local_tree(base: &Path) -> io::Result<BTreeMap<String, PathBuf>> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![(base.to_path_buf(), String::new())];
+    while let Some((dir, rel_dir)) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let rel = if rel_dir.is_empty() { name } else { format!("{}/{}", rel_dir, name) };
+            if entry.file_type()?.is_dir() {
+                dirs.push((entry.path(), rel));
+            } else {
+                files.insert(rel, entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Create remote directory, an existing one is fine.
+pub fn create_dir(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if is_dry_run() {
+        return yandex_disk_dry_run::plan_create_dir(url, conf, path);
+    }
+    let rclient = api_client();
+    let resp = send_request(rclient, rclient.put(
+        format!("{}/resources?path={}", url, utf8_percent_encode(path, NON_ALPHANUMERIC)).as_str())
+        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?)))?;
+
+    match resp.status() {
+        reqwest::StatusCode::CREATED => {
+            info!("Created directory {}", path.bright_yellow());
+            Ok(())
+        }
+        reqwest::StatusCode::CONFLICT => Ok(()),
+        status => Err(format!("Can't create directory {}: {}", path, status).into()),
+    }
+}
+
+/// Bytes as B, KiB, MiB, ... with one decimal place.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// "1024", "10K", "1.5M", "2G", "1T" (binary units) to bytes.
+pub fn parse_size(s: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().find(|(_i, c)| c.is_ascii_alphabetic()) {
+        Some((i, _c)) => {
+            let multiplier: u64 = match s[i..].to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+                "" => 1,
+                "K" => 1 << 10,
+                "M" => 1 << 20,
+                "G" => 1 << 30,
+                "T" => 1 << 40,
+                _ => return Err(format!("Bad size: {}", s).into()),
+            };
+            (&s[..i], multiplier)
+        }
+        None => (s, 1),
+    };
+    let number: f64 = number.trim().parse().map_err(|_| format!("Bad size: {}", s))?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+//
+// Quota
+//
+
+/// Check planned uploads (name, size) against max file size and free space of the Disk.
+pub fn check_upload_quota(disk: &YaDisk, files: &[(String, u64)]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut problems: Vec<String> = Vec::new();
+
+    if let Some(max_file_size) = disk.max_file_size {
+        for (name, size) in files.iter().filter(|(_name, size)| *size > max_file_size) {
+            problems.push(format!("{} is {}, max file size is {}", name, human_size(*size), human_size(max_file_size)));
+        }
+    }
+
+    let total: u64 = files.iter().map(|(_name, size)| size).sum();
+    let free = disk.free_space();
+    if total > free {
+        problems.push(format!("{} to upload, only {} free: {} short",
+                              human_size(total), human_size(free), human_size(total - free)));
+    }
+
+    if problems.is_empty() {
+        debug!("{} of {} free space to be used", human_size(total), human_size(free));
+        Ok(())
+    } else {
+        Err(format!("Upload won't fit (use --force to try anyway):\n  {}", problems.join("\n  ")).into())
+    }
+}
+
+//
+// Integrity
+//
+
+/// Transferred data doesn't match checksums known to the Disk.
+#[derive(Debug)]
+pub struct IntegrityError(pub String);
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Integrity check failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+#[derive(Clone)]
+pub struct TransferOptions {
+    pub overwrite: bool,
+    /// Refuse uploads that won't fit into free space or max file size before sending anything
+    pub check_quota: bool,
+    /// Compare checksums of transferred data with the ones the Disk reports
+    pub verify: bool,
+    /// How many times to repeat a transfer that failed verification
+    pub retries: u32,
+    /// Upload files bigger than `split_size` as parts, see `yandex_disk_split`
+    pub split: bool,
+    /// Part size, max file size of the Disk if not set
+    pub split_size: Option<u64>,
+    /// Parts uploaded at once
+    pub parallel: usize,
+    /// Encrypt uploaded content with `key`, see `yandex_disk_crypt`
+    pub encrypt: bool,
+    /// Encrypt names of uploaded files and directories too
+    pub encrypt_names: bool,
+    /// Key for encrypted uploads, and for downloads of encrypted content
+    pub key: Option<yandex_disk_crypt::Key>,
+    /// Compress uploaded content, see `yandex_disk_compress`
+    pub compress: Option<Compression>,
+    /// Extensions of files never compressed
+    pub compress_exclude: Vec<String>,
+    /// Copy stored files with the same content instead of uploading, see `yandex_disk_dedupe`
+    pub dedupe: Option<yandex_disk_dedupe::SharedIndex>,
+    /// Counter of bytes sent and received
+    pub progress: Option<Progress>,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        TransferOptions {
+            overwrite: false,
+            check_quota: true,
+            verify: true,
+            retries: 0,
+            split: false,
+            split_size: None,
+            parallel: 4,
+            encrypt: false,
+            encrypt_names: false,
+            key: None,
+            compress: None,
+            compress_exclude: yandex_disk_compress::DEFAULT_EXCLUDE.iter().map(|x| x.to_string()).collect(),
+            dedupe: None,
+            progress: None,
+        }
+    }
+}
+
+/// Compare local checksums with the ones of a remote resource.
+pub fn compare_checksums(local: &Checksums, remote: &Resource) -> Result<(), IntegrityError> {
+    if let Some(size) = remote.size.filter(|x| *x != local.size) {
+        return Err(IntegrityError(format!("{}: size {} != {}", remote.path, local.size, size)));
+    }
+    if let Some(md5) = remote.md5.as_ref().filter(|x| **x != local.md5) {
+        return Err(IntegrityError(format!("{}: md5 {} != {}", remote.path, local.md5, md5)));
+    }
+    if let Some(sha256) = remote.sha256.as_ref().filter(|x| **x != local.sha256) {
+        return Err(IntegrityError(format!("{}: sha256 {} != {}", remote.path, local.sha256, sha256)));
+    }
+    Ok(())
+}
+
+fn verify_remote_checksums(
+    url: &str,
+    conf: &config::Config,
+    remote_path: &str,
+    local: &Checksums,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let remote = get_resource(url, conf, remote_path, Some(CHECKSUM_FIELDS))?;
+    if remote.md5.is_none() && remote.sha256.is_none() {
+        warn!("No checksums for {} on the Disk, only size is checked", remote_path);
+    }
+    compare_checksums(local, &remote)?;
+    debug!("{} verified, md5 {}", remote_path, local.md5);
+    Ok(())
+}
+
+fn is_integrity_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    e.downcast_ref::<IntegrityError>().is_some()
+}
+
+/// Local path meaning STDIN for uploads and STDOUT for downloads
+pub const STDIO_PATH: &str = "-";
+
 pub fn upload_file(
     url: &str,
     conf: &config::Config,
     local_path: &str,
     remote_path: &str,
-    overwrite_flag: bool,
+    options: &TransferOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
 
-    println!("Attempting to upload:\nLocal:{}\nTo remote:{}", local_path, remote_path);
+    // Size of STDIN is unknown until it's sent
+    let size = if local_path == STDIO_PATH { None } else { Some(std::fs::metadata(local_path)?.len()) };
+
+    if let (true, Some(size)) = (options.check_quota, size) {
+        let disk = get_disk_info(conf)?;
+        let part_size = if options.split { Some(yandex_disk_split::part_size(&disk, options.split_size, options.encrypt)?) } else { None };
+        check_upload_quota(&disk, &yandex_disk_split::planned_parts(local_path, size, part_size, options.encrypt))?;
+    }
+
+    // Stored content is only the same when it's stored as is
+    let dedupe = options.dedupe.as_ref().filter(|_| size.is_some() && !options.encrypt && options.compress.is_none());
+    if let Some(index) = dedupe {
+        if yandex_disk_dedupe::upload_by_copy(url, conf, index, local_path, remote_path, options.overwrite)? {
+            return Ok(());
+        }
+    }
+
+    if let (true, Some(size)) = (options.split, size) {
+        let part_size = match options.split_size {
+            Some(x) => x,
+            None => yandex_disk_split::part_size(&get_disk_info(conf)?, None, options.encrypt)?,
+        };
+        if size > part_size {
+            // Parts are stored uncompressed, their sizes must be known
+            let stored_path = stored_path(remote_path, None, options)?;
+            if is_dry_run() {
+                return yandex_disk_dry_run::plan_upload(url, conf, local_path,
+                    yandex_disk_split::split_dir(stored_path.as_str()).as_str(), Some(size), options.overwrite);
+            }
+            return yandex_disk_split::upload_split(url, conf, local_path, stored_path.as_str(), part_size, options);
+        }
+    }
+
+    let compression = options.compress.filter(|_| {
+        let name = if local_path == STDIO_PATH { remote_path } else { local_path };
+        !yandex_disk_compress::is_excluded(name, &options.compress_exclude)
+    });
+    let stored_path = stored_path(remote_path, compression, options)?;
+    let remote_path = stored_path.as_str();
+    if is_dry_run() {
+        return yandex_disk_dry_run::plan_upload(url, conf, local_path, remote_path, size, options.overwrite);
+    }
+    info!("Uploading {} to {}", local_path, remote_path.bright_yellow());
+
+    // STDIN is consumed by the first attempt, nothing to retry with
+    let sums = upload_verified(url, conf, remote_path, options, local_path != STDIO_PATH, |digests| {
+        if local_path == STDIO_PATH {
+            // Unknown length, reqwest sends it with chunked transfer encoding
+            upload_body(io::stdin(), None, compression, options, digests)
+        } else {
+            upload_body(File::open(local_path)?, size, compression, options, digests)
+        }
+    })?;
+    if let Some(index) = dedupe {
+        index.lock().unwrap().insert(&sums, remote_path);
+    }
+
+    if let Some(compression) = compression {
+        set_custom_property(url, conf, remote_path, yandex_disk_compress::COMPRESSION_PROPERTY, compression.as_str())?;
+    }
+    Ok(())
+}
+
+fn encryption_key(options: &TransferOptions) -> Result<&yandex_disk_crypt::Key, Box<dyn std::error::Error>> {
+    Ok(options.key.as_ref().ok_or_else(|| {
+        format!("No encryption key: use --key-file or set {}", yandex_disk_crypt::PASSPHRASE_ENV)
+    })?)
+}
+
+/// Remote path of an upload, the name is changed for compressed and encrypted ones
+fn stored_path(
+    remote_path: &str,
+    compression: Option<Compression>,
+    options: &TransferOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let remote_path = format!("{}{}", remote_path, compression.map_or("", |x| x.extension()));
+    if !options.encrypt {
+        return Ok(remote_path);
+    }
+    let key = encryption_key(options)?;
+    let remote_path = remote_path.as_str();
+    Ok(match remote_path.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/{}", dir, yandex_disk_crypt::stored_name(name, key, options.encrypt_names)),
+        None => yandex_disk_crypt::stored_name(remote_path, key, options.encrypt_names),
+    })
+}
+
+/// Request body of `size` bytes (if known) from `reader`, compressed with `compression`
+/// and encrypted if `options` say so. `digests` get the data actually sent.
+fn upload_body<R: io::Read + Send + 'static>(
+    reader: R,
+    size: Option<u64>,
+    compression: Option<Compression>,
+    options: &TransferOptions,
+    digests: SharedDigests,
+) -> Result<reqwest::blocking::Body, Box<dyn std::error::Error>> {
+    fn body<R: io::Read + Send + 'static>(reader: R, size: Option<u64>) -> reqwest::blocking::Body {
+        match size {
+            Some(x) => reqwest::blocking::Body::sized(reader, x),
+            None => reqwest::blocking::Body::new(reader),
+        }
+    }
+
+    // Compressed size is unknown until it's sent
+    let (reader, size): (Box<dyn io::Read + Send>, Option<u64>) = match compression {
+        Some(x) => (x.reader(reader)?, None),
+        None => (Box::new(reader), size),
+    };
+    let reader: Box<dyn io::Read + Send> = match options.progress.clone() {
+        Some(progress) => Box::new(ProgressReader::new(reader, progress)),
+        None => reader,
+    };
+
+    if options.encrypt {
+        let reader = yandex_disk_crypt::EncryptingReader::new(reader, encryption_key(options)?)?;
+        Ok(body(HashingReader::new(reader, digests), size.map(yandex_disk_crypt::encrypted_size)))
+    } else {
+        Ok(body(HashingReader::new(reader, digests), size))
+    }
+}
+
+/// Upload body made by `make_body` and compare checksums of the sent data with the ones of the Disk.
+/// The upload is repeated on mismatch up to `options.retries` times if it's `retryable`.
+fn upload_verified<F>(
+    url: &str,
+    conf: &config::Config,
+    remote_path: &str,
+    options: &TransferOptions,
+    retryable: bool,
+    make_body: F,
+) -> Result<Checksums, Box<dyn std::error::Error>>
+where
+    F: Fn(SharedDigests) -> Result<reqwest::blocking::Body, Box<dyn std::error::Error>>,
+{
+    let mut overwrite_flag = options.overwrite;
+    let mut attempt = 0;
+    loop {
+        let digests = SharedDigests::new();
+        upload_stream(url, conf, make_body(digests.clone())?, remote_path, overwrite_flag)?;
+        let local = digests.finish();
+
+        if !options.verify {
+            return Ok(local);
+        }
+        match verify_remote_checksums(url, conf, remote_path, &local) {
+            Err(e) if is_integrity_error(e.as_ref()) && attempt < options.retries && retryable => {
+                attempt += 1;
+                warn!("{}, retrying ({}/{})", e, attempt, options.retries);
+                // The broken copy is ours, replace it
+                overwrite_flag = true;
+            }
+            result => return result.map(|()| local),
+        }
+    }
+}
+
+/// Upload every file below `local_dir` keeping the directory structure.
+pub fn upload_dir(
+    url: &str,
+    conf: &config::Config,
+    local_dir: &str,
+    remote_dir: &str,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let files = walk_local_tree(Path::new(local_dir))?;
+    let remote_dir = remote_dir.trim_end_matches('/');
+
+    // Part size is resolved once, not for every file
+    let mut split_size = options.split_size;
+    if options.check_quota || (options.split && split_size.is_none()) {
+        let disk = get_disk_info(conf)?;
+        if options.split {
+            split_size = Some(yandex_disk_split::part_size(&disk, split_size, options.encrypt)?);
+        }
+        if options.check_quota {
+            let mut planned: Vec<(String, u64)> = Vec::new();
+            for path in files.values() {
+                planned.extend(yandex_disk_split::planned_parts(&path.to_string_lossy(), path.metadata()?.len(), split_size, options.encrypt));
+            }
+            check_upload_quota(&disk, &planned)?;
+        }
+    }
+
+    let file_options = TransferOptions { check_quota: false, split_size, ..options.clone() };
+
+    create_dir(url, conf, remote_dir)?;
+    let mut created: std::collections::BTreeSet<String> = std::collections::BTreeSet::new();
+    for (rel, path) in files.iter() {
+        // Directory names are encrypted here, file names by upload_file
+        let mut components: Vec<String> = rel.split('/').map(|x| x.to_string()).collect();
+        if options.encrypt && options.encrypt_names {
+            let key = encryption_key(options)?;
+            let dirs = components.len() - 1;
+            for x in components[..dirs].iter_mut() {
+                *x = yandex_disk_crypt::encrypt_name(x, key);
+            }
+        }
+        // Parents first: "a", "a/b" for "a/b/file"
+        for depth in 1..components.len() {
+            let parent = components[..depth].join("/");
+            if created.insert(parent.clone()) {
+                create_dir(url, conf, format!("{}/{}", remote_dir, parent).as_str())?;
+            }
+        }
+        upload_file(url, conf, path.to_str().ok_or("Non UTF-8 local path")?,
+                    format!("{}/{}", remote_dir, components.join("/")).as_str(), &file_options)?;
+    }
+    Ok(())
+}
+
+/// Upload request body of known (file) or unknown (any reader) length to `remote_path`.
+pub fn upload_stream(
+    url: &str,
+    conf: &config::Config,
+    body: reqwest::blocking::Body,
+    remote_path: &str,
+    overwrite_flag: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     let s:String = make_api_request(
             format!(
                 "{}/resources/upload?path={}&overwrite={}",
@@ -113,45 +906,393 @@ pub fn upload_file(
         , conf)?;
     let ui:UploadInfo = serde_json::from_str(s.as_str())?;
 
-    println!("{:#?}", ui);
+    debug!("{:#?}", ui);
+
+    let client = transfer_client();
+    let resp = send_request(client, client.put(&ui.href).body(body))?;
+
+    if resp.status().is_success() {
+        info!("Upload done: {}", resp.status());
+        Ok(())
+    } else {
+        Err(format!("Upload failed: {}", resp.status()).into())
+    }
+}
+
+//
+// Upload from URL
+//
 
-    let file = File::open(local_path)?;
-    let client = reqwest::blocking::Client::new();
-    let res = client.put(&ui.href)
-                .body(file)
-                .send();
+const OPERATION_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
 
-    println!("{:#?}", res);
+/// Ask Yandex Disk to fetch `source_url` by itself and store it at `remote_path`.
+/// Returns the link to the started operation.
+pub fn start_upload_from_url(
+    url: &str,
+    conf: &config::Config,
+    source_url: &str,
+    remote_path: &str,
+) -> Result<Link, Box<dyn std::error::Error>> {
+    let s: String = make_api_call(reqwest::Method::POST,
+        format!(
+            "{}/resources/upload?url={}&path={}",
+            url,
+            utf8_percent_encode(source_url, NON_ALPHANUMERIC),
+            utf8_percent_encode(remote_path, NON_ALPHANUMERIC)).as_str()
+        , conf)?;
+    Ok(serde_json::from_str(s.as_str())?)
+}
 
-    Ok(())
+pub fn get_operation_status(
+    conf: &config::Config,
+    operation: &Link,
+) -> Result<OperationStatus, Box<dyn std::error::Error>> {
+    let s: String = make_api_request(operation.href.as_str(), conf)?;
+    let op: Operation = serde_json::from_str(s.as_str())?;
+    Ok(op.status)
+}
+
+/// Poll operation until it leaves the "in-progress" state, returns the final status.
+pub fn wait_for_operation(
+    conf: &config::Config,
+    operation: &Link,
+) -> Result<OperationStatus, Box<dyn std::error::Error>> {
+    loop {
+        let status = get_operation_status(conf, operation)?;
+        if status != OperationStatus::InProgress {
+            // The revision seen while it ran is already behind
+            yandex_disk_cache::invalidate();
+            return Ok(status);
+        }
+        debug!("Operation is still in progress");
+        std::thread::sleep(OPERATION_POLL_INTERVAL);
+    }
+}
+
+fn print_operation_outcome(source_url: &str, remote_path: &str, success: bool, status: &str) {
+    let status = if success { status.green() } else { status.red() };
+    println!("{:10} {} -> {}", status, source_url, remote_path.blue());
+}
+
+pub fn upload_from_url(
+    url: &str,
+    conf: &config::Config,
+    source_url: &str,
+    remote_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if is_dry_run() {
+        yandex_disk_dry_run::plan_import(source_url, remote_path);
+        return Ok(());
+    }
+    info!("Importing {} to {}", redact_url(source_url), remote_path.bright_yellow());
+    let op = start_upload_from_url(url, conf, source_url, remote_path)?;
+    let status = wait_for_operation(conf, &op)?;
+    let success = status == OperationStatus::Success;
+    print_operation_outcome(source_url, remote_path, success, status.to_string().as_str());
+
+    if success {
+        Ok(())
+    } else {
+        Err(format!("Upload from URL finished with status: {}", status).into())
+    }
+}
+
+/// Remote name for a URL import when it isn't given explicitly: last segment of the URL path.
+fn remote_name_from_url(source_url: &str) -> Option<String> {
+    let parsed = Url::parse(source_url).ok()?;
+    let segment = parsed.path_segments()?.rev().find(|x| !x.is_empty())?;
+    Some(percent_encoding::percent_decode_str(segment).decode_utf8_lossy().to_string())
+}
+
+/// Queue imports from a list file. Every non-empty line holds a URL and an optional
+/// remote path; URLs without a remote path are stored in `remote_dir` under their own name.
+/// Lines starting with '#' are ignored.
+pub fn upload_from_url_list(
+    url: &str,
+    conf: &config::Config,
+    list_path: &str,
+    remote_dir: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let list = std::fs::read_to_string(list_path)?;
+
+    let mut imports: Vec<(String, String)> = Vec::new();
+    for line in list.lines().map(str::trim).filter(|x| !x.is_empty() && !x.starts_with('#')) {
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let source_url = parts.next().unwrap().to_string();
+        let remote_path = match parts.next().map(str::trim) {
+            Some(p) if !p.is_empty() => p.to_string(),
+            _ => match remote_name_from_url(source_url.as_str()) {
+                Some(name) => format!("{}/{}", remote_dir.trim_end_matches('/'), name),
+                None => {
+                    warn!("Can't guess file name for {}, skipping", redact_url(source_url.as_str()));
+                    continue;
+                }
+            },
+        };
+        imports.push((source_url, remote_path));
+    }
+
+    if is_dry_run() {
+        imports.iter().for_each(|(source_url, remote_path)| yandex_disk_dry_run::plan_import(source_url, remote_path));
+        return Ok(());
+    }
+
+    // Start everything first, the Disk fetches files in parallel on its side
+    let mut operations: Vec<(&str, &str, Result<Link, String>)> = Vec::new();
+    for (source_url, remote_path) in imports.iter() {
+        info!("Queueing {} to {}", redact_url(source_url), remote_path.bright_yellow());
+        operations.push((source_url, remote_path,
+                         start_upload_from_url(url, conf, source_url, remote_path).map_err(|e| e.to_string())));
+    }
+
+    let mut failed = 0;
+    for (source_url, remote_path, op) in operations.iter() {
+        let (success, status) = match op {
+            Ok(link) => match wait_for_operation(conf, link) {
+                Ok(status) => (status == OperationStatus::Success, status.to_string()),
+                Err(e) => (false, e.to_string()),
+            },
+            Err(e) => (false, e.clone()),
+        };
+        if !success {
+            failed += 1;
+        }
+        print_operation_outcome(source_url, remote_path, success, status.as_str());
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} of {} imports failed", failed, operations.len()).into())
+    }
+}
+
+/// Finish a call that may complete right away (`done` status) or start an async operation (202 with a Link).
+fn wait_for_response(
+    conf: &config::Config,
+    resp: reqwest::blocking::Response,
+    done: reqwest::StatusCode,
+    what: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let status = resp.status();
+    if status == done {
+        Ok(())
+    } else if status == reqwest::StatusCode::ACCEPTED {
+        let op: Link = serde_json::from_str(resp.text()?.as_str())?;
+        match wait_for_operation(conf, &op)? {
+            OperationStatus::Success => Ok(()),
+            op_status => Err(format!("{} finished with status: {}", what, op_status).into()),
+        }
+    } else {
+        debug!("{}", resp.text()?);
+        Err(format!("{} failed: {}", what, status).into())
+    }
 }
 
 pub fn delete_remote_file(
     url: &str,
-    oauth_token: &str,
+    conf: &config::Config,
     remote_path: &str,
     permanently_flag: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if is_dry_run() {
+        return yandex_disk_dry_run::plan_delete(url, conf, remote_path, permanently_flag);
+    }
 
-    println!("Trying to delete: {}", remote_path.bright_yellow());
+    info!("Deleting {}", remote_path.bright_yellow());
 
-    let rclient = reqwest::blocking::Client::new();
-    let resp = rclient.delete(
+    let rclient = api_client();
+    let resp = send_request(rclient, rclient.delete(
         format!(
-            "{}/resources?path={}&force_async=false&permanently={}",
+            "{}/resources?path={}&permanently={}",
             url,
             utf8_percent_encode(remote_path, NON_ALPHANUMERIC).to_string().as_str(),
             permanently_flag).as_str())
-        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", oauth_token))
-        .send()?;
+        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?)))?;
+
+    wait_for_response(conf, resp, reqwest::StatusCode::NO_CONTENT, "Delete")?;
+    info!("Deleted {}", remote_path);
+    Ok(())
+}
+
+pub fn move_resource(
+    url: &str,
+    conf: &config::Config,
+    from: &str,
+    to: &str,
+    overwrite_flag: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if is_dry_run() {
+        return yandex_disk_dry_run::plan_relocate(url, conf, "move", from, to, overwrite_flag);
+    }
+
+    info!("Moving {} to {}", from, to.bright_yellow());
+
+    let rclient = api_client();
+    let resp = send_request(rclient, rclient.post(
+        format!(
+            "{}/resources/move?from={}&path={}&overwrite={}",
+            url,
+            utf8_percent_encode(from, NON_ALPHANUMERIC),
+            utf8_percent_encode(to, NON_ALPHANUMERIC),
+            overwrite_flag).as_str())
+        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?)))?;
+
+    wait_for_response(conf, resp, reqwest::StatusCode::CREATED, "Move")
+}
+
+/// Copy a resource on the server side, no data is transferred.
+pub fn copy_resource(
+    url: &str,
+    conf: &config::Config,
+    from: &str,
+    to: &str,
+    overwrite_flag: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if is_dry_run() {
+        return yandex_disk_dry_run::plan_relocate(url, conf, "copy", from, to, overwrite_flag);
+    }
+
+    debug!("Copying {} to {}", from, to);
+
+    let rclient = api_client();
+    let resp = send_request(rclient, rclient.post(
+        format!(
+            "{}/resources/copy?from={}&path={}&overwrite={}",
+            url,
+            utf8_percent_encode(from, NON_ALPHANUMERIC),
+            utf8_percent_encode(to, NON_ALPHANUMERIC),
+            overwrite_flag).as_str())
+        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?)))?;
+
+    wait_for_response(conf, resp, reqwest::StatusCode::CREATED, "Copy")
+}
+
+/// Set custom property of a resource, other properties are kept
+pub fn set_custom_property(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    name: &str,
+    value: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut properties = serde_json::Map::new();
+    properties.insert(name.to_string(), serde_json::Value::from(value));
+    let mut body = serde_json::Map::new();
+    body.insert("custom_properties".to_string(), serde_json::Value::Object(properties));
+
+    make_api_call_with_body(reqwest::Method::PATCH,
+                            ResourceQuery::resource(path).fields(Some(&["custom_properties"])).url(url).as_str(),
+                            conf, Some(&serde_json::Value::Object(body)))?;
+    debug!("{}: {} = {}", path, name, value);
+    Ok(())
+}
+
+/// Publish a resource, returns its public URL
+pub fn publish_resource(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if is_dry_run() {
+        yandex_disk_dry_run::plan_publish(url, conf, "publish", path)?;
+        return Ok(format!("<public URL of {}>", path));
+    }
+    make_api_call(reqwest::Method::PUT,
+                  format!("{}/resources/publish?path={}", url, utf8_percent_encode(path, NON_ALPHANUMERIC)).as_str(), conf)?;
+    let resource = get_resource(url, conf, path, Some(&["public_url"]))?;
+    info!("Published {}", path.bright_yellow());
+    Ok(resource.public_url.ok_or_else(|| format!("{} is published, but has no public URL", path))?)
+}
+
+pub fn unpublish_resource(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if is_dry_run() {
+        return yandex_disk_dry_run::plan_publish(url, conf, "unpublish", path);
+    }
+    make_api_call(reqwest::Method::PUT,
+                  format!("{}/resources/unpublish?path={}", url, utf8_percent_encode(path, NON_ALPHANUMERIC)).as_str(), conf)?;
+    info!("Unpublished {}", path.bright_yellow());
+    Ok(())
+}
+
+/// Compression of a file uploaded compressed: it has the extension and the custom property of it.
+/// The property is only fetched for names with such an extension.
+fn stored_compression(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    options: &TransferOptions,
+) -> Result<Option<Compression>, Box<dyn std::error::Error>> {
+    let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    let compression = match Compression::from_name(&yandex_disk_crypt::decrypt_name(name, options.key.as_ref())) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let resource = get_resource(url, conf, path, Some(&["custom_properties"]))?;
+    let marked = resource.custom_properties.as_ref()
+        .and_then(|x| x.get(yandex_disk_compress::COMPRESSION_PROPERTY))
+        .and_then(|x| x.as_str());
+    Ok(if marked == Some(compression.as_str()) { Some(compression) } else { None })
+}
+
+pub fn get_download_response(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+) -> Result<(DownloadInfo, reqwest::blocking::Response), Box<dyn std::error::Error>> {
+    let s:String = make_api_request([url, "/resources/download?path=", utf8_percent_encode(path, NON_ALPHANUMERIC).to_string().as_str()].concat().as_str(), conf)?;
+    let di:DownloadInfo = serde_json::from_str(s.as_str())?;
+
+    let rclient = transfer_client();
+    let resp = send_request(rclient, rclient.get(&di.href)
+        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?)))?;
 
     if resp.status() == reqwest::StatusCode::OK {
-        println!("OK");
-        Ok(())
+        debug!("Data size: {}", resp.headers().get("content-length").map_or("unknown", |v| v.to_str().unwrap_or("unknown")));
+        Ok((di, resp))
     } else {
-        println!("--Response status is not OK\n{:#?}\n{:#?}", resp.status(), resp.text());
-        Err("Response status is not OK".to_string().into())
+        Err(format!("Response status is not OK: {}", resp.status()).into())
+    }
+}
+
+/// Body of a download response, counted in `options.progress`
+fn received(resp: reqwest::blocking::Response, options: &TransferOptions) -> Box<dyn io::Read> {
+    match options.progress.clone() {
+        Some(progress) => Box::new(ProgressReader::new(resp, progress)),
+        None => Box::new(resp),
+    }
+}
+
+/// Stream remote file into `out` decrypting it if needed, returns number of bytes received.
+pub fn download_to_writer(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    out: &mut dyn io::Write,
+    options: &TransferOptions,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let (_di, resp) = match get_download_response(url, conf, path) {
+        Ok(x) => x,
+        Err(e) => match yandex_disk_split::read_manifest(url, conf, path, options) {
+            Some(manifest) => return yandex_disk_split::download_split(url, conf, path, &manifest, out, options),
+            None => return Err(e),
+        },
+    };
+    // Checksums of the Disk are the ones of stored, maybe compressed and encrypted, data
+    let digests = SharedDigests::new();
+    let compression = stored_compression(url, conf, path, options)?;
+    let mut plain = yandex_disk_crypt::DecryptingWriter::new(
+        yandex_disk_compress::DecompressingWriter::new(&mut *out, compression)?, options.key.clone());
+    let size = io::copy(&mut received(resp, options), &mut HashingWriter::new(&mut plain, digests.clone()))?;
+    plain.finish()?.finish()?;
+    if options.verify {
+        verify_remote_checksums(url, conf, path, &digests.finish())?;
     }
+    Ok(size)
 }
 
 pub fn download_file(
@@ -159,32 +1300,208 @@ pub fn download_file(
     conf: &config::Config,
     path: &str, 
     target_path: Option<&str>,
+    options: &TransferOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
 
-    println!("Attempting to download:\nRemote:{}\nTo:{}", path, target_path.unwrap_or_default());
-    let s:String = make_api_request([url, "/resources/download?path=", utf8_percent_encode(path, NON_ALPHANUMERIC).to_string().as_str()].concat().as_str(), conf)?;
-    let di:DownloadInfo = serde_json::from_str(s.as_str())?;
+    debug!("Downloading {} to {}", path, target_path.unwrap_or_default());
 
+    if is_dry_run() {
+        return yandex_disk_dry_run::plan_download(url, conf, path, target_path, options);
+    }
+    if target_path == Some(STDIO_PATH) {
+        return cat_file(url, conf, path, options);
+    }
 
-    let rclient = reqwest::blocking::Client::new();
-    let mut resp = rclient.get(&di.href)
-        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?))
-        .send()?;
+    let mut attempt = 0;
+    loop {
+        let (di, resp) = match get_download_response(url, conf, path) {
+            Ok(x) => x,
+            // Not a file, maybe parts of a split one
+            Err(e) => match yandex_disk_split::read_manifest(url, conf, path, options) {
+                Some(manifest) => return yandex_disk_split::download_split_file(url, conf, path, &manifest, target_path, options),
+                None => return Err(e),
+            },
+        };
 
-    if resp.status() == reqwest::StatusCode::OK {
-        println!("Download request done. Data size: {}", resp.headers().get("content-length").unwrap().to_str()?);
-        
         let parsed = Url::parse(&di.href)?;
         let filename = parsed.query_pairs().find(|(x,_y)| x=="filename").unwrap().1.to_string();
-        let target = target_path.or(Some(filename.as_str())).unwrap();
+        let filename = yandex_disk_crypt::decrypt_name(filename.as_str(), options.key.as_ref());
+        let compression = stored_compression(url, conf, path, options)?;
+        let filename = compression.and_then(|x| filename.strip_suffix(x.extension())).unwrap_or(filename.as_str());
+        let target = target_path.unwrap_or(filename);
 
-        println!("Saving as {}", target);
+        info!("Saving as {}", target);
 
-        let mut out = File::create(target).expect("failed to create file");
-        io::copy(&mut resp, &mut out).expect("failed to copy content");
-    } else {
-        println!("--Responce status is not OK\n{:#?}", resp.status());
+        let digests = SharedDigests::new();
+        let mut plain = yandex_disk_crypt::DecryptingWriter::new(
+            yandex_disk_compress::DecompressingWriter::new(File::create(target)?, compression)?, options.key.clone());
+        io::copy(&mut received(resp, options), &mut HashingWriter::new(&mut plain, digests.clone()))?;
+        plain.finish()?.finish()?;
+
+        if !options.verify {
+            return Ok(());
+        }
+        match verify_remote_checksums(url, conf, path, &digests.finish()) {
+            Err(e) if is_integrity_error(e.as_ref()) => {
+                std::fs::remove_file(target)?;
+                if attempt >= options.retries {
+                    return Err(e);
+                }
+                attempt += 1;
+                warn!("{}, retrying ({}/{})", e, attempt, options.retries);
+            }
+            result => return result,
+        }
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// Write remote file to STDOUT. A closed pipe (`| head`) is not an error.
+pub fn cat_file(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match download_to_writer(url, conf, path, &mut out, options) {
+        Ok(size) => {
+            debug!("{} bytes written to STDOUT", size);
+            Ok(())
+        }
+        Err(e) => match e.downcast_ref::<io::Error>() {
+            Some(io_err) if io_err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            _ => Err(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yandex_disk_test_util::resource;
+
+    fn abc() -> Checksums {
+        Checksums {
+            md5: "900150983cd24fb0d6963f7d28e17f72".to_string(),
+            sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+            size: 3,
+        }
+    }
+
+    /// Pages of `limit` raw items at an offset, the second one can't be decoded
+    fn pages(raw: usize, limit: usize, offset: usize) -> Items<Resource> {
+        let items: Vec<serde_json::Value> = (offset..raw.min(offset + limit))
+            .map(|i| if i == 1 {
+                serde_json::json!({ "path": "disk:/broken", "type": "file" })
+            } else {
+                serde_json::to_value(yandex_disk_test_util::file(&format!("disk:/{}", i), 1, None)).unwrap()
+            })
+            .collect();
+        serde_json::from_value(serde_json::Value::Array(items)).unwrap()
+    }
+
+    #[test]
+    fn paging_counts_skipped_items() {
+        let mut offsets = Vec::new();
+        let items = collect_pages(2, |offset| {
+            offsets.push(offset);
+            Ok((pages(5, 2, offset), None))
+        }).unwrap();
+        assert_eq!(offsets, vec![0, 2, 4]);
+        let paths: Vec<&str> = items.iter().map(|x| x.path.as_str()).collect();
+        assert_eq!(paths, ["disk:/0", "disk:/2", "disk:/3", "disk:/4"]);
+
+        // A full last page ends the listing at the total
+        let mut offsets = Vec::new();
+        let items = collect_pages(2, |offset| {
+            offsets.push(offset);
+            Ok((pages(4, 2, offset), Some(4)))
+        }).unwrap();
+        assert_eq!(offsets, vec![0, 2]);
+        assert_eq!(items.len(), 3);
+    }
+
+    #[test]
+    fn redacted_urls() {
+        let url = "https://downloader.disk.yandex.ru/disk/abc?uid=123&sign=s3cr3t&filename=a.txt";
+        let redacted = redact_url(url);
+        assert!(!redacted.contains("s3cr3t") && !redacted.contains("123"), "{}", redacted);
+        assert!(redacted.starts_with("https://downloader.disk.yandex.ru/disk/abc?uid="), "{}", redacted);
+        assert!(redacted.contains("sign=") && redacted.contains("filename="), "{}", redacted);
+
+        assert_eq!(redact_url("https://cloud-api.yandex.net/v1/disk"), "https://cloud-api.yandex.net/v1/disk");
+        assert_eq!(redact_url("not a url?token=s3cr3t"), format!("not a url?{}", REDACTED));
+    }
+
+    #[test]
+    fn redacted_headers() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::AUTHORIZATION, "OAuth s3cr3t".parse().unwrap());
+        headers.insert(reqwest::header::CONTENT_LENGTH, "42".parse().unwrap());
+        let redacted = redact_headers(&headers);
+        assert!(!redacted.contains("s3cr3t"), "{}", redacted);
+        assert!(redacted.contains(&format!("authorization: {}", REDACTED)), "{}", redacted);
+        assert!(redacted.contains("content-length: 42"), "{}", redacted);
+    }
+
+    fn disk(max_file_size: u64, total_space: u64, used_space: u64) -> YaDisk {
+        serde_json::from_value(serde_json::json!({
+            "max_file_size": max_file_size,
+            "total_space": total_space,
+            "used_space": used_space,
+        }))
+        .unwrap()
+    }
+
+    fn planned(sizes: &[u64]) -> Vec<(String, u64)> {
+        sizes.iter().enumerate().map(|(i, size)| (format!("file{}", i), *size)).collect()
+    }
+
+    #[test]
+    fn quota() {
+        let disk = disk(100, 1000, 700);
+        assert!(check_upload_quota(&disk, &planned(&[100, 100, 100])).is_ok());
+        assert!(check_upload_quota(&disk, &[]).is_ok());
+
+        let too_big = check_upload_quota(&disk, &planned(&[10, 101])).unwrap_err().to_string();
+        assert!(too_big.contains("file1") && too_big.contains("max file size"), "{}", too_big);
+        assert!(!too_big.contains("file0"), "{}", too_big);
+
+        let no_space = check_upload_quota(&disk, &planned(&[100, 100, 100, 1])).unwrap_err().to_string();
+        assert!(no_space.contains("only 300 B free"), "{}", no_space);
+        assert!(!no_space.contains("max file size"), "{}", no_space);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("10K").unwrap(), 10 << 10);
+        assert_eq!(parse_size("1.5M").unwrap(), 3 << 19);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size(" 5 mb ").unwrap(), 5 << 20);
+        assert_eq!(parse_size("1T").unwrap(), 1 << 40);
+        for bad in ["", "K", "10X", "ten"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        let local = abc();
+        let remote = |attributes| resource("disk:/abc", attributes);
+        assert!(compare_checksums(&local, &remote(serde_json::json!({
+            "size": 3, "md5": local.md5, "sha256": local.sha256,
+        }))).is_ok());
+        // Only what the Disk reported is compared
+        assert!(compare_checksums(&local, &remote(serde_json::json!({}))).is_ok());
+        assert!(compare_checksums(&local, &remote(serde_json::json!({ "size": 4 }))).is_err());
+        assert!(compare_checksums(&local, &remote(serde_json::json!({
+            "size": 3, "md5": "00000000000000000000000000000000",
+        }))).is_err());
+        let mismatch = compare_checksums(&local, &remote(serde_json::json!({
+            "size": 3, "md5": local.md5, "sha256": "0".repeat(64),
+        })));
+        assert!(mismatch.unwrap_err().to_string().contains("sha256"));
+    }
+}
@@ -0,0 +1,614 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, FixedOffset};
+use sha2::{Digest, Sha256};
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::{Read, Write};
+
+use super::*;
+
+//
+// Backup repository
+//
+// Files are cut into content-defined chunks, every chunk is stored once by its hash.
+// A snapshot is a manifest listing files and their chunks, an unchanged file costs
+// nothing but its line in the next snapshot. Layout of a repository:
+//
+//   config.json              repository version, chunking parameters, encryption
+//   chunks/<ab>/<abcdef...>  chunk with id "abcdef...", encrypted in encrypted repositories
+//   snapshots/<id>.json      snapshot manifest, encrypted in encrypted repositories
+//
+// Chunk ids of encrypted repositories are keyed hashes, so names don't reveal content.
+//
+
+const CONFIG_NAME: &str = "config.json";
+const REPOSITORY_VERSION: u32 = 1;
+
+pub const MIN_CHUNK: usize = 1024 * 1024;
+/// Bits of the rolling hash that must be zero for a cut, 22 is 4 MiB chunks on average
+pub const CHUNK_BITS: u32 = 22;
+pub const MAX_CHUNK: usize = 16 * 1024 * 1024;
+
+//
+// Chunking
+//
+
+/// Gear hash table, the same for everybody: chunks of a repository must never move
+fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut state: u64 = 0x5961_4469_736b_4344;
+    let mut table = [0u64; 256];
+    for x in table.iter_mut() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        *x = z ^ (z >> 31);
+    }
+    table
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ChunkParams {
+    pub min: usize,
+    pub bits: u32,
+    pub max: usize,
+}
+
+impl Default for ChunkParams {
+    fn default() -> Self {
+        ChunkParams { min: MIN_CHUNK, bits: CHUNK_BITS, max: MAX_CHUNK }
+    }
+}
+
+/// Content-defined chunks of a reader: a cut is made where a gear hash of the last bytes
+/// has its low `bits` zero, so an insertion only changes chunks around it.
+pub struct Chunker<R> {
+    inner: R,
+    params: ChunkParams,
+    gear: [u64; 256],
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(inner: R, params: ChunkParams) -> Self {
+        Chunker { inner, params, gear: gear_table(), buf: Vec::new(), eof: false }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        while !self.eof && self.buf.len() < self.params.max {
+            let len = self.buf.len();
+            self.buf.resize(self.params.max, 0);
+            match self.inner.read(&mut self.buf[len..]) {
+                Ok(0) => {
+                    self.buf.truncate(len);
+                    self.eof = true;
+                }
+                Ok(n) => self.buf.truncate(len + n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => self.buf.truncate(len),
+                Err(e) => {
+                    self.buf.truncate(len);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn cut_point(&self) -> usize {
+        let end = self.buf.len().min(self.params.max);
+        if end <= self.params.min {
+            return end;
+        }
+        let mask = (1u64 << self.params.bits) - 1;
+        let mut hash: u64 = 0;
+        for (i, &b) in self.buf[self.params.min..end].iter().enumerate() {
+            hash = (hash << 1).wrapping_add(self.gear[b as usize]);
+            if hash & mask == 0 {
+                return self.params.min + i + 1;
+            }
+        }
+        end
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            return Some(Err(e));
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+        let cut = self.cut_point();
+        let rest = self.buf.split_off(cut);
+        Some(Ok(std::mem::replace(&mut self.buf, rest)))
+    }
+}
+
+//
+// Repository
+//
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RepositoryConfig {
+    pub version: u32,
+    pub chunking: ChunkParams,
+    pub encrypted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotFile {
+    /// Path relative to the backed up directory, '/' separated
+    pub path: String,
+    pub size: u64,
+    pub modified: Option<DateTime<FixedOffset>>,
+    pub chunks: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub id: String,
+    pub time: DateTime<FixedOffset>,
+    /// Backed up local directory
+    pub source: String,
+    pub files: Vec<SnapshotFile>,
+}
+
+impl Snapshot {
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|x| x.size).sum()
+    }
+}
+
+/// Repository at a remote path with the options its data is transferred with
+pub struct Repository<'a> {
+    url: &'a str,
+    conf: &'a config::Config,
+    path: String,
+    config: RepositoryConfig,
+    options: TransferOptions,
+}
+
+fn chunk_path(repo: &str, id: &str) -> String {
+    format!("{}/chunks/{}/{}", repo, &id[..2], id)
+}
+
+fn snapshot_path(repo: &str, id: &str) -> String {
+    format!("{}/snapshots/{}.json", repo, id)
+}
+
+fn read_remote(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    options: &TransferOptions,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    download_to_writer(url, conf, path, &mut data, options)?;
+    Ok(data)
+}
+
+fn write_remote(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    data: Vec<u8>,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Chunks, snapshots and the config of a new repository are new files
+    if is_dry_run() {
+        yandex_disk_dry_run::print_action("create", path, format!("({})", human_size(data.len() as u64)).as_str());
+        return Ok(());
+    }
+    upload_verified(url, conf, path, options, true, |digests| {
+        upload_body(io::Cursor::new(data.clone()), Some(data.len() as u64), None, options, digests)
+    })?;
+    Ok(())
+}
+
+impl<'a> Repository<'a> {
+    /// Open the repository at `path`, creating it if there is none and `create` is set.
+    /// A new repository is encrypted if `options.encrypt` is, an existing one keeps its setting.
+    pub fn open(
+        url: &'a str,
+        conf: &'a config::Config,
+        path: &str,
+        create: bool,
+        options: &TransferOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.trim_end_matches('/').to_string();
+        let config_path = format!("{}/{}", path, CONFIG_NAME);
+        // Only a missing config means there's no repository, any other error is passed on
+        let config: RepositoryConfig = match yandex_disk_dry_run::find_resource(url, conf, config_path.as_str())? {
+            Some(_) => serde_json::from_slice(&read_remote(url, conf, config_path.as_str(), options)?)?,
+            None if !create => return Err(format!("No backup repository at {}", path).into()),
+            None => {
+                let config = RepositoryConfig {
+                    version: REPOSITORY_VERSION,
+                    chunking: ChunkParams::default(),
+                    encrypted: options.encrypt,
+                };
+                for dir in [path.clone(), format!("{}/chunks", path), format!("{}/snapshots", path)].iter() {
+                    create_dir(url, conf, dir)?;
+                }
+                let plain = TransferOptions { encrypt: false, ..options.clone() };
+                write_remote(url, conf, config_path.as_str(), serde_json::to_vec_pretty(&config)?, &plain)?;
+                info!("Created backup repository {}{}", path.bright_yellow(), if config.encrypted { " (encrypted)" } else { "" });
+                config
+            }
+        };
+        if config.version != REPOSITORY_VERSION {
+            return Err(format!("{}: unsupported repository version {}", path, config.version).into());
+        }
+        if config.encrypted {
+            encryption_key(options)?;
+        }
+
+        // Content is addressed by hash: what's there under the name is what would be uploaded
+        let options = TransferOptions {
+            overwrite: true,
+            check_quota: false,
+            split: false,
+            encrypt: config.encrypted,
+            encrypt_names: false,
+            compress: None,
+            ..options.clone()
+        };
+        Ok(Repository { url, conf, path, config, options })
+    }
+
+    fn chunk_id(&self, data: &[u8]) -> String {
+        match self.options.key.as_ref().filter(|_| self.config.encrypted) {
+            Some(key) => yandex_disk_crypt::keyed_hash(data, key),
+            None => format!("{:x}", Sha256::digest(data)),
+        }
+    }
+
+    /// Whether `dir` of a repository created in a dry run is only planned
+    fn is_planned(&self, dir: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(is_dry_run() && yandex_disk_dry_run::find_resource(self.url, self.conf, dir)?.is_none())
+    }
+
+    /// Ids of stored chunks with their stored sizes
+    pub fn stored_chunks(&self) -> Result<BTreeMap<String, u64>, Box<dyn std::error::Error>> {
+        let chunks = format!("{}/chunks", self.path);
+        if self.is_planned(chunks.as_str())? {
+            return Ok(BTreeMap::new());
+        }
+        Ok(walk_remote(self.url, self.conf, chunks.as_str(), Some(SIZE_FIELDS))?
+            .into_values()
+            .map(|item| {
+                let size = item.file_size();
+                (item.name, size)
+            })
+            .collect())
+    }
+
+    /// Ids of snapshots, oldest first
+    pub fn snapshot_ids(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let snapshots = format!("{}/snapshots", self.path);
+        if self.is_planned(snapshots.as_str())? {
+            return Ok(Vec::new());
+        }
+        let mut ids: Vec<String> = list_dir(self.url, self.conf, snapshots.as_str(), Some(REQUIRED_FIELDS))?
+            .into_iter()
+            .filter_map(|item| item.name.strip_suffix(".json").map(|x| x.to_string()))
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn snapshot(&self, id: &str) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        let data = read_remote(self.url, self.conf, snapshot_path(&self.path, id).as_str(), &self.options)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>, Box<dyn std::error::Error>> {
+        self.snapshot_ids()?.iter().map(|id| self.snapshot(id)).collect()
+    }
+
+    /// Chunk data, checked against its id
+    pub fn read_chunk(&self, id: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let data = read_remote(self.url, self.conf, chunk_path(&self.path, id).as_str(), &self.options)?;
+        let actual = self.chunk_id(&data);
+        if actual != id {
+            return Err(IntegrityError(format!("chunk {} has content of {}", id, actual)).into());
+        }
+        Ok(data)
+    }
+}
+
+//
+// Commands
+//
+
+/// `id`, or with the first free "-N" suffix if a snapshot was made in the same second
+fn unique_id(id: String, existing: &[String]) -> String {
+    if !existing.contains(&id) {
+        return id;
+    }
+    (2..).map(|n| format!("{}-{}", id, n)).find(|x| !existing.contains(x)).unwrap()
+}
+
+/// Back up every file below `local_dir` as a new snapshot, returns its id.
+pub fn create_snapshot(
+    url: &str,
+    conf: &config::Config,
+    repo: &str,
+    local_dir: &str,
+    options: &TransferOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let repo = Repository::open(url, conf, repo, true, options)?;
+    let files = walk_local_tree(Path::new(local_dir))?;
+    let mut known: HashSet<String> = repo.stored_chunks()?.into_keys().collect();
+    let mut dirs: HashSet<String> = HashSet::new();
+    info!("Backing up {} files of {}, {} chunks stored", files.len(), local_dir, known.len());
+
+    let time = chrono::Local::now().fixed_offset();
+    let mut snapshot = Snapshot {
+        id: unique_id(time.format("%Y%m%d-%H%M%S").to_string(), &repo.snapshot_ids()?),
+        time,
+        source: local_dir.to_string(),
+        files: Vec::new(),
+    };
+    let (mut new_chunks, mut new_bytes) = (0, 0);
+    for (rel, path) in files.iter() {
+        let metadata = std::fs::metadata(path)?;
+        let mut chunks = Vec::new();
+        for chunk in Chunker::new(io::BufReader::new(File::open(path)?), repo.config.chunking) {
+            let chunk = chunk?;
+            let id = repo.chunk_id(&chunk);
+            if !known.contains(&id) {
+                let dir = format!("{}/chunks/{}", repo.path, &id[..2]);
+                if !dirs.contains(&dir) {
+                    create_dir(url, conf, dir.as_str())?;
+                    dirs.insert(dir);
+                }
+                new_chunks += 1;
+                new_bytes += chunk.len() as u64;
+                write_remote(url, conf, chunk_path(&repo.path, &id).as_str(), chunk, &repo.options)?;
+                known.insert(id.clone());
+            }
+            chunks.push(id);
+        }
+        debug!("{}: {} chunks", rel, chunks.len());
+        snapshot.files.push(SnapshotFile {
+            path: rel.clone(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(|x| DateTime::<chrono::Utc>::from(x).fixed_offset()),
+            chunks,
+        });
+    }
+
+    write_remote(url, conf, snapshot_path(&repo.path, &snapshot.id).as_str(), serde_json::to_vec_pretty(&snapshot)?, &repo.options)?;
+    println!("Snapshot {}: {} files, {}, {} new chunks ({})",
+             snapshot.id.bright_green(), snapshot.files.len(), human_size(snapshot.size()), new_chunks, human_size(new_bytes));
+    Ok(snapshot.id)
+}
+
+pub fn list_snapshots(
+    url: &str,
+    conf: &config::Config,
+    repo: &str,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let repo = Repository::open(url, conf, repo, false, options)?;
+    for snapshot in repo.snapshots()? {
+        println!("{}  {}  {:>6} files  {:>10}  {}",
+                 snapshot.id.bright_green(), snapshot.time.format("%Y-%m-%d %H:%M:%S"),
+                 snapshot.files.len(), human_size(snapshot.size()), snapshot.source);
+    }
+    Ok(())
+}
+
+/// Where a file of a snapshot is restored, manifest paths must not leave `target_dir`
+fn restore_target(target_dir: &str, path: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let rel = Path::new(path);
+    if path.is_empty() || !rel.components().all(|x| matches!(x, std::path::Component::Normal(_))) {
+        return Err(format!("Snapshot has unsafe path {:?}", path).into());
+    }
+    Ok(Path::new(target_dir).join(rel))
+}
+
+/// Restore files of a snapshot into `target_dir`.
+pub fn restore_snapshot(
+    url: &str,
+    conf: &config::Config,
+    repo: &str,
+    id: &str,
+    target_dir: &str,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let repo = Repository::open(url, conf, repo, false, options)?;
+    let snapshot = repo.snapshot(id)?;
+    info!("Restoring {} files of snapshot {} into {}", snapshot.files.len(), id, target_dir);
+
+    // Checked before anything is written
+    let targets = snapshot.files.iter()
+        .map(|file| restore_target(target_dir, file.path.as_str()))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (file, target) in snapshot.files.iter().zip(targets) {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut out = File::create(&target)?;
+        let mut size = 0;
+        for chunk in file.chunks.iter() {
+            let data = repo.read_chunk(chunk)?;
+            out.write_all(&data)?;
+            size += data.len() as u64;
+        }
+        if size != file.size {
+            return Err(IntegrityError(format!("{}: restored size {} != {}", file.path, size, file.size)).into());
+        }
+        if let Some(modified) = file.modified {
+            out.set_modified(modified.into())?;
+        }
+        debug!("{} restored", file.path);
+    }
+    println!("Restored {} files, {}", snapshot.files.len(), human_size(snapshot.size()));
+    Ok(())
+}
+
+/// Delete given snapshots, and all but the `keep_last` latest ones if set.
+/// Chunks stay until `prune`.
+pub fn forget_snapshots(
+    url: &str,
+    conf: &config::Config,
+    repo: &str,
+    ids: &[&str],
+    keep_last: Option<usize>,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let repo = Repository::open(url, conf, repo, false, options)?;
+    let existing = repo.snapshot_ids()?;
+    let mut forget: BTreeSet<&str> = BTreeSet::new();
+    for id in ids.iter() {
+        if !existing.iter().any(|x| x == id) {
+            return Err(format!("No snapshot {}", id).into());
+        }
+        forget.insert(id);
+    }
+    if let Some(keep) = keep_last {
+        forget.extend(existing.iter().rev().skip(keep).map(|x| x.as_str()));
+    }
+
+    for id in forget.iter() {
+        delete_remote_file(url, conf, snapshot_path(&repo.path, id).as_str(), true)?;
+        println!("Forgot snapshot {}", id.bright_yellow());
+    }
+    Ok(())
+}
+
+/// Delete chunks no snapshot refers to.
+pub fn prune(
+    url: &str,
+    conf: &config::Config,
+    repo: &str,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let repo = Repository::open(url, conf, repo, false, options)?;
+    let used: HashSet<String> = repo.snapshots()?
+        .into_iter()
+        .flat_map(|x| x.files.into_iter().flat_map(|f| f.chunks))
+        .collect();
+    let (mut count, mut bytes) = (0, 0);
+    for (id, size) in repo.stored_chunks()? {
+        if !used.contains(&id) {
+            delete_remote_file(url, conf, chunk_path(&repo.path, &id).as_str(), true)?;
+            count += 1;
+            bytes += size;
+        }
+    }
+    println!("Pruned {} chunks, {}", count, human_size(bytes));
+    Ok(())
+}
+
+/// Check that every chunk snapshots refer to is stored, and with `read_data` that its content matches its id.
+pub fn check(
+    url: &str,
+    conf: &config::Config,
+    repo: &str,
+    read_data: bool,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let repo = Repository::open(url, conf, repo, false, options)?;
+    let stored = repo.stored_chunks()?;
+    let mut problems: Vec<String> = Vec::new();
+    let mut checked: HashSet<String> = HashSet::new();
+    let snapshots = repo.snapshots()?;
+
+    for snapshot in snapshots.iter() {
+        for file in snapshot.files.iter() {
+            for chunk in file.chunks.iter() {
+                if !checked.insert(chunk.clone()) {
+                    continue;
+                }
+                if !stored.contains_key(chunk) {
+                    problems.push(format!("{}: {}: chunk {} is missing", snapshot.id, file.path, chunk));
+                } else if read_data {
+                    if let Err(e) = repo.read_chunk(chunk) {
+                        problems.push(format!("{}: {}: {}", snapshot.id, file.path, e));
+                    }
+                }
+            }
+        }
+    }
+
+    let unused = stored.keys().filter(|x| !checked.contains(*x)).count();
+    println!("{} snapshots, {} chunks referenced, {} unused", snapshots.len(), checked.len(), unused);
+    if problems.is_empty() {
+        println!("{}", "No problems found".green());
+        Ok(())
+    } else {
+        for problem in problems.iter() {
+            println!("{}", problem.red());
+        }
+        Err(format!("{} problems found", problems.len()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: ChunkParams = ChunkParams { min: 2 * 1024, bits: 13, max: 32 * 1024 };
+
+    fn random_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        Chunker::new(data, PARAMS).collect::<io::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn restore_targets() {
+        assert_eq!(restore_target("out", "a/b.txt").unwrap(), Path::new("out/a/b.txt"));
+        for path in ["", "../x", "a/../../x", "/etc/passwd", "./a", "a/./b/.."] {
+            assert!(restore_target("out", path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn unique_ids() {
+        let existing = vec!["20201024-031500".to_string(), "20201024-031500-2".to_string()];
+        assert_eq!(unique_id("20201024-031501".to_string(), &existing), "20201024-031501");
+        assert_eq!(unique_id("20201024-031500".to_string(), &existing), "20201024-031500-3");
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        let data = random_data(1024 * 1024, 1);
+        let chunks = chunks(&data);
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks.iter().all(|x| x.len() <= PARAMS.max));
+        assert!(chunks[..chunks.len() - 1].iter().all(|x| x.len() >= PARAMS.min));
+        assert!(chunks.len() > 50 && chunks.len() < 200, "{} chunks", chunks.len());
+        assert!(Chunker::new(&[][..], PARAMS).next().is_none());
+    }
+
+    #[test]
+    fn insertion_keeps_other_chunks() {
+        let data = random_data(1024 * 1024, 2);
+        let mut changed = data.clone();
+        changed.splice(500_000..500_000, b"inserted".iter().copied());
+
+        let before: HashSet<Vec<u8>> = chunks(&data).into_iter().collect();
+        let after = chunks(&changed);
+        let new = after.iter().filter(|x| !before.contains(*x)).count();
+        assert!(new <= 2, "{} of {} chunks changed", new, after.len());
+    }
+}
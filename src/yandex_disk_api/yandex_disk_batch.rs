@@ -0,0 +1,409 @@
+use serde::{Deserialize, Serialize};
+
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::*;
+
+//
+// Batch operations
+//
+// A batch file holds one operation per line, either as words ("mv /a /b") or as a JSON
+// object ({"op": "mv", "from": "/a", "to": "/b"}). Every operation gets a line in the result
+// log, in the format it was given in, and the log can be run as a batch again: operations
+// that succeeded are commented out (or marked "ok") and only the others are repeated.
+//
+
+const LINE_HELP: &str = "\
+mkdir PATH
+upload LOCAL REMOTE
+download REMOTE [LOCAL]
+mv FROM TO
+cp FROM TO
+rm PATH
+publish PATH";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Mkdir { path: String },
+    Upload { from: String, to: String },
+    Download {
+        from: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+    },
+    Mv { from: String, to: String },
+    Cp { from: String, to: String },
+    /// Moves to trash
+    Rm { path: String },
+    Publish { path: String },
+}
+
+impl Operation {
+    /// Operation of a line split into words
+    pub fn from_words(words: &[String]) -> Result<Self, String> {
+        let arg = |i: usize| words.get(i).cloned();
+        let operation = match (words.first().map_or("", String::as_str), words.len()) {
+            ("mkdir", 2) => Operation::Mkdir { path: words[1].clone() },
+            ("upload", 3) => Operation::Upload { from: words[1].clone(), to: words[2].clone() },
+            ("download", 2) | ("download", 3) => Operation::Download { from: words[1].clone(), to: arg(2) },
+            ("mv", 3) => Operation::Mv { from: words[1].clone(), to: words[2].clone() },
+            ("cp", 3) => Operation::Cp { from: words[1].clone(), to: words[2].clone() },
+            ("rm", 2) => Operation::Rm { path: words[1].clone() },
+            ("publish", 2) => Operation::Publish { path: words[1].clone() },
+            _ => return Err(format!("Expected one of:\n{}", LINE_HELP)),
+        };
+        Ok(operation)
+    }
+
+    pub fn words(&self) -> Vec<&str> {
+        match self {
+            Operation::Mkdir { path } => vec!["mkdir", path],
+            Operation::Upload { from, to } => vec!["upload", from, to],
+            Operation::Download { from, to } => ["download", from.as_str()].iter().copied().chain(to.as_deref()).collect(),
+            Operation::Mv { from, to } => vec!["mv", from, to],
+            Operation::Cp { from, to } => vec!["cp", from, to],
+            Operation::Rm { path } => vec!["rm", path],
+            Operation::Publish { path } => vec!["publish", path],
+        }
+    }
+
+    /// Remote paths the operation reads or changes
+    fn remote_paths(&self) -> Vec<&str> {
+        match self {
+            Operation::Mkdir { path } | Operation::Rm { path } | Operation::Publish { path } => vec![path],
+            Operation::Upload { to, .. } => vec![to],
+            Operation::Download { from, .. } => vec![from],
+            Operation::Mv { from, to } | Operation::Cp { from, to } => vec![from, to],
+        }
+    }
+
+    /// Local files read or written, a download without a target saves under the remote name
+    fn local_paths(&self) -> Vec<&str> {
+        match self {
+            Operation::Upload { from, .. } => vec![from],
+            Operation::Download { to: Some(to), .. } => vec![to],
+            Operation::Download { from, to: None } => vec![from.trim_end_matches('/').rsplit('/').next().unwrap_or_default()],
+            _ => vec![],
+        }
+    }
+
+    /// Whether both touch the same remote or local path, or one a path inside the other
+    fn overlaps(&self, other: &Operation) -> bool {
+        let nested = |a: &[String], b: &[String]| a.iter().any(|a| b.iter().any(|b| a.starts_with(b) || b.starts_with(a)));
+        let remote = |op: &Operation| -> Vec<String> {
+            op.remote_paths().iter().map(|x| format!("{}/", x.strip_prefix("disk:").unwrap_or(x).trim_end_matches('/'))).collect()
+        };
+        let local = |op: &Operation| -> Vec<String> {
+            op.local_paths().iter().map(|x| format!("{}/", x.trim_start_matches("./").trim_end_matches('/'))).collect()
+        };
+        nested(&remote(self), &remote(other)) || nested(&local(self), &local(other))
+    }
+
+    /// Line of words, quoted where `split_args` needs it
+    pub fn to_line(&self) -> String {
+        self.words().iter()
+            .map(|x| {
+                if !x.is_empty() && !x.contains(|c: char| c.is_whitespace() || "\"'\\#".contains(c)) {
+                    x.to_string()
+                } else {
+                    format!("\"{}\"", x.replace('\\', "\\\\").replace('"', "\\\""))
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Failed,
+    /// Not run after an error with --stop-on-error
+    Skipped,
+}
+
+/// JSON line of a batch or of a result log
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonEntry {
+    #[serde(flatten)]
+    operation: Operation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_url: Option<String>,
+}
+
+/// Operation of a batch with where it comes from
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// Line number, from 1
+    pub line: usize,
+    pub json: bool,
+    pub operation: Operation,
+}
+
+/// Operations of a batch. Empty lines, comments and JSON entries that succeeded before are
+/// left out. Any malformed line fails the whole batch before anything is run.
+pub fn parse_batch(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in text.lines().enumerate().map(|(i, x)| (i + 1, x.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = if line.starts_with('{') {
+            serde_json::from_str::<JsonEntry>(line)
+                .map_err(|e| e.to_string())
+                .map(|x| if x.status == Some(Status::Ok) { None } else { Some((true, x.operation)) })
+        } else {
+            yandex_disk_shell::split_args(line)
+                .and_then(|words| Operation::from_words(&words))
+                .map(|x| Some((false, x)))
+        };
+        match parsed {
+            Ok(Some((json, operation))) => entries.push(Entry { line: i, json, operation }),
+            Ok(None) => (),
+            Err(e) => errors.push(format!("line {}: {}", i, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(entries)
+    } else {
+        Err(format!("Bad batch:\n{}", errors.join("\n")))
+    }
+}
+
+/// Result log lines of an entry, in the format of the entry
+pub fn result_text(entry: &Entry, status: Status, error: Option<&str>, public_url: Option<&str>) -> String {
+    if entry.json {
+        let result = JsonEntry {
+            operation: entry.operation.clone(),
+            status: Some(status),
+            error: error.map(String::from),
+            public_url: public_url.map(String::from),
+        };
+        return format!("{}\n", serde_json::to_string(&result).unwrap_or_default());
+    }
+    let line = entry.operation.to_line();
+    match status {
+        Status::Ok => match public_url {
+            Some(x) => format!("# ok: {} -> {}\n", line, x),
+            None => format!("# ok: {}\n", line),
+        },
+        Status::Failed => format!("# failed: {}\n{}\n", error.unwrap_or_default().replace('\n', " "), line),
+        Status::Skipped => format!("# skipped\n{}\n", line),
+    }
+}
+
+/// Ranges of operations run together. A `mkdir` waits for everything before it,
+/// and everything after it waits for the `mkdir`. An operation touching a path of one
+/// earlier in the stage waits for it too: "upload a /a" then "mv /a /b" stay in order.
+pub fn stages(entries: &[Entry]) -> Vec<Range<usize>> {
+    let mut stages = Vec::new();
+    let mut start = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let barrier = matches!(entry.operation, Operation::Mkdir { .. });
+        if start < i && (barrier || entries[start..i].iter().any(|x| x.operation.overlaps(&entry.operation))) {
+            stages.push(start..i);
+            start = i;
+        }
+        if barrier {
+            stages.push(i..i + 1);
+            start = i + 1;
+        }
+    }
+    if start < entries.len() {
+        stages.push(start..entries.len());
+    }
+    stages
+}
+
+/// Run one operation, returns the public URL of published resources
+pub fn execute(
+    url: &str,
+    conf: &config::Config,
+    operation: &Operation,
+    options: &TransferOptions,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match operation {
+        Operation::Mkdir { path } => create_dir(url, conf, path)?,
+        Operation::Upload { from, to } if Path::new(from).is_dir() => upload_dir(url, conf, from, to, options)?,
+        Operation::Upload { from, to } => upload_file(url, conf, from, to, options)?,
+        Operation::Download { from, to } => download_file(url, conf, from, to.as_deref(), options)?,
+        Operation::Mv { from, to } => move_resource(url, conf, from, to, options.overwrite)?,
+        Operation::Cp { from, to } => copy_resource(url, conf, from, to, options.overwrite)?,
+        Operation::Rm { path } => delete_remote_file(url, conf, path, false)?,
+        Operation::Publish { path } => return Ok(Some(publish_resource(url, conf, path)?)),
+    }
+    Ok(None)
+}
+
+/// Result log written in the order of the batch, whatever order operations finish in
+struct ResultLog {
+    out: Box<dyn Write + Send>,
+    results: Vec<Option<String>>,
+    written: usize,
+    counts: [usize; 3],
+}
+
+impl ResultLog {
+    fn record(&mut self, index: usize, status: Status, text: String) -> io::Result<()> {
+        self.counts[status as usize] += 1;
+        self.results[index] = Some(text);
+        while let Some(text) = self.results.get_mut(self.written).and_then(Option::take) {
+            self.out.write_all(text.as_bytes())?;
+            self.written += 1;
+        }
+        self.out.flush()
+    }
+}
+
+/// Run operations of a batch file (STDIN for '-') `jobs` at a time and write the result log
+/// to `results` (STDOUT if not given). After a failure with `stop_on_error` nothing else is
+/// started, the rest is logged as skipped.
+pub fn batch(
+    url: &str,
+    conf: &config::Config,
+    source: &str,
+    results: Option<&str>,
+    jobs: usize,
+    stop_on_error: bool,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut text = String::new();
+    if source == STDIO_PATH {
+        io::stdin().read_to_string(&mut text)?;
+    } else {
+        text = std::fs::read_to_string(source).map_err(|e| format!("{}: {}", source, e))?;
+    }
+    let entries = parse_batch(text.as_str())?;
+    info!("{} operations to run", entries.len());
+
+    // Nothing is done in a dry run, a log of it would mark operations as done
+    let out: Box<dyn Write + Send> = match results {
+        _ if is_dry_run() => Box::new(io::sink()),
+        Some(path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => Box::new(io::stdout()),
+    };
+    let log = Mutex::new(ResultLog { out, results: vec![None; entries.len()], written: 0, counts: [0; 3] });
+    let stopped = AtomicBool::new(false);
+    let errors: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    for stage in stages(&entries) {
+        let next = AtomicUsize::new(stage.start);
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.clamp(1, stage.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        if index >= stage.end {
+                            break;
+                        }
+                        let entry = &entries[index];
+                        let (status, error, public_url) = if stopped.load(Ordering::SeqCst) {
+                            (Status::Skipped, None, None)
+                        } else {
+                            match execute(url, conf, &entry.operation, options) {
+                                Ok(public_url) => (Status::Ok, None, public_url),
+                                Err(e) => {
+                                    error!("line {}: {}: {}", entry.line, entry.operation.to_line(), e);
+                                    if stop_on_error {
+                                        stopped.store(true, Ordering::SeqCst);
+                                    }
+                                    (Status::Failed, Some(e.to_string()), None)
+                                }
+                            }
+                        };
+                        let text = result_text(entry, status, error.as_deref(), public_url.as_deref());
+                        if let Err(e) = log.lock().unwrap().record(index, status, text) {
+                            errors.lock().unwrap().push(e.to_string());
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    if let Some(e) = errors.into_inner().unwrap().first() {
+        return Err(format!("Can't write result log: {}", e).into());
+    }
+    let [ok, failed, skipped] = log.into_inner().unwrap().counts;
+    info!("{} operations done, {} failed, {} skipped", ok, failed, skipped);
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} of {} operations failed", failed, entries.len()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let entries = parse_batch(r#"
+# comment
+mkdir /a
+upload "my file.txt" '/a/my file.txt'
+{"op": "mv", "from": "/a/x", "to": "/a/y"}
+{"op": "rm", "path": "/a/old", "status": "ok"}
+{"op": "download", "from": "/a/y", "status": "failed", "error": "Response status is not OK: 404"}
+"#).unwrap();
+        assert_eq!(entries, vec![
+            Entry { line: 3, json: false, operation: Operation::Mkdir { path: "/a".to_string() } },
+            Entry { line: 4, json: false, operation: Operation::Upload { from: "my file.txt".to_string(), to: "/a/my file.txt".to_string() } },
+            Entry { line: 5, json: true, operation: Operation::Mv { from: "/a/x".to_string(), to: "/a/y".to_string() } },
+            Entry { line: 7, json: true, operation: Operation::Download { from: "/a/y".to_string(), to: None } },
+        ]);
+
+        let e = parse_batch("mkdir /a\nmove /a /b\n{\"op\": \"rm\"}\nupload x").unwrap_err();
+        assert_eq!(e.lines().filter(|x| x.starts_with("line ")).count(), 3);
+    }
+
+    #[test]
+    fn results_run_again() {
+        let entries = parse_batch("upload \"a \\\"b\\\"\" /c\n{\"op\": \"publish\", \"path\": \"/p\"}").unwrap();
+        let failed = result_text(&entries[0], Status::Failed, Some("Upload failed: 507"), None);
+        assert_eq!(failed, "# failed: Upload failed: 507\nupload \"a \\\"b\\\"\" /c\n");
+        let published = result_text(&entries[1], Status::Ok, None, Some("https://yadi.sk/d/x"));
+        assert_eq!(published, "{\"op\":\"publish\",\"path\":\"/p\",\"status\":\"ok\",\"public_url\":\"https://yadi.sk/d/x\"}\n");
+        let skipped = result_text(&entries[1], Status::Skipped, None, None);
+
+        // Only what didn't succeed is run again
+        let again = parse_batch(format!("{}{}{}", failed, published, skipped).as_str()).unwrap();
+        assert_eq!(again.iter().map(|x| &x.operation).collect::<Vec<_>>(), vec![&entries[0].operation, &entries[1].operation]);
+    }
+
+    #[test]
+    fn mkdir_is_a_barrier() {
+        let entries = parse_batch("upload a /a\nupload b /b\nmkdir /c\nmkdir /d\nupload c /c/c\ncp /a /c/a").unwrap();
+        assert_eq!(stages(&entries), vec![0..2, 2..3, 3..4, 4..6]);
+        assert_eq!(stages(&[]), vec![]);
+    }
+
+    #[test]
+    fn overlapping_paths_wait() {
+        let entries = parse_batch("upload a /a\nmv /a /b\nupload c /c\nrm disk:/b/x/\ncp /d /e\npublish /ab").unwrap();
+        assert_eq!(stages(&entries), vec![0..1, 1..3, 3..6]);
+        let entries = parse_batch("upload a /a\nupload b /b\nmkdir /c\nupload c /c/c\ndownload /c/c").unwrap();
+        assert_eq!(stages(&entries), vec![0..2, 2..3, 3..4, 4..5]);
+    }
+
+    #[test]
+    fn overlapping_local_paths_wait() {
+        let entries = parse_batch("download /a/x.txt
+upload ./x.txt /b
+download /c out
+download /d out/d
+upload y /y").unwrap();
+        assert_eq!(stages(&entries), vec![0..1, 1..3, 3..5]);
+    }
+}
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::*;
+
+//
+// Metadata cache
+//
+// Listing responses are kept on disk with the revision of the Disk they were fetched at.
+// Every change of the Disk bumps its revision, so one cheap call per run tells whether
+// all cached listings are still good. Offline runs take whatever was cached last.
+// Accounts are cached apart, each under a hash of its token.
+//
+
+/// Cached listings older than this are fetched again even at the same revision, `cache_ttl` setting
+const DEFAULT_TTL_SECS: u64 = 24 * 60 * 60;
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Listings fetched less than this many seconds ago are taken without checking the revision
+static TRUSTED_AGE: AtomicU64 = AtomicU64::new(0);
+
+/// Revisions of the Disks known in this run by account, forgotten after changes made by the run
+static REVISIONS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Cache directory of the client: $XDG_CACHE_HOME/yadisk-client or ~/.cache/yadisk-client
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(x) if !x.is_empty() => PathBuf::from(x),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("yadisk-client"))
+}
+
+/// Serve listings from the cache only and refuse any request
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Take listings fetched in the last `secs` seconds as they are, without asking for the revision.
+/// For quick repeated lookups like completion, where a listing a few seconds old is good enough.
+pub fn trust_recent(secs: u64) {
+    TRUSTED_AGE.store(secs, Ordering::Relaxed);
+}
+
+/// Forget the known revisions after a change, the next lookup asks for them again
+pub fn invalidate() {
+    REVISIONS.lock().unwrap().clear();
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    revision: u64,
+    /// Unix time of the request
+    fetched: u64,
+    body: String,
+}
+
+impl Entry {
+    fn is_fresh(&self, revision: u64, now: u64, ttl: u64) -> bool {
+        self.revision == revision && self.is_recent(now, ttl)
+    }
+
+    fn is_recent(&self, now: u64, secs: u64) -> bool {
+        now.saturating_sub(self.fetched) < secs
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs())
+}
+
+/// Key of the account `conf` works with, the token isn't kept in the clear
+fn account(conf: &config::Config) -> Result<String, Box<dyn std::error::Error>> {
+    let mut hasher = Sha256::new();
+    hasher.update(conf.get_str("url")?.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(conf.get_str("oauth_token")?.as_bytes());
+    Ok(format!("{:x}", hasher.finalize())[..32].to_string())
+}
+
+fn entry_path(account: &str, url: &str) -> Option<PathBuf> {
+    Some(cache_dir()?.join("metadata").join(account).join(format!("{:x}.json", Sha256::digest(url.as_bytes()))))
+}
+
+fn read_entry(path: &Path, url: &str) -> Option<Entry> {
+    let entry: Entry = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+    if entry.url != url {
+        return None;
+    }
+    Some(entry)
+}
+
+fn write_entry(path: &Path, entry: &Entry) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_vec(entry)?)
+}
+
+fn current_revision(account: &str, conf: &config::Config) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut revisions = REVISIONS.lock().unwrap();
+    if let Some(x) = revisions.get(account) {
+        return Ok(*x);
+    }
+    let disk: YaDisk = serde_json::from_str(make_api_request(format!("{}?fields=revision", conf.get_str("url")?).as_str(), conf)?.as_str())?;
+    let x = disk.revision.ok_or("Disk revision is unknown")?;
+    debug!("Disk revision is {}", x);
+    revisions.insert(account.to_string(), x);
+    Ok(x)
+}
+
+/// GET of a listing, answered from the cache while the Disk is at the same revision.
+/// Caching is turned off by `metadata_cache = false` in config.
+pub fn cached_api_request(url: &str, conf: &config::Config) -> Result<String, Box<dyn std::error::Error>> {
+    let account = account(conf)?;
+    let path = entry_path(&account, url);
+    if is_offline() {
+        return path.as_deref()
+            .and_then(|x| read_entry(x, url))
+            .map(|x| x.body)
+            .ok_or_else(|| "Listing is not cached, run it once without --offline".into());
+    }
+    if !conf.get_bool("metadata_cache").unwrap_or(true) {
+        return make_api_request(url, conf);
+    }
+
+    let trusted_age = TRUSTED_AGE.load(Ordering::Relaxed);
+    if let Some(entry) = path.as_deref().and_then(|x| read_entry(x, url)).filter(|x| x.is_recent(now(), trusted_age)) {
+        debug!("Recently cached {}", redact_url(url));
+        return Ok(entry.body);
+    }
+
+    // Taken before the request: a change in between makes the entry stale, not wrong
+    let revision = current_revision(&account, conf)?;
+    let ttl = conf.get_int("cache_ttl").map_or(DEFAULT_TTL_SECS, |x| x.max(0) as u64);
+    if let Some(entry) = path.as_deref().and_then(|x| read_entry(x, url)).filter(|x| x.is_fresh(revision, now(), ttl)) {
+        debug!("Cached {}", redact_url(url));
+        return Ok(entry.body);
+    }
+
+    let body = make_api_request(url, conf)?;
+    if let Some(path) = path {
+        // Listings work without the cache too
+        let entry = Entry { url: url.to_string(), revision, fetched: now(), body };
+        if let Err(e) = write_entry(&path, &entry) {
+            debug!("Can't cache {}: {}", redact_url(url), e);
+        }
+        return Ok(entry.body);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freshness() {
+        let entry = Entry { url: "u".to_string(), revision: 7, fetched: 1000, body: String::new() };
+        assert!(entry.is_fresh(7, 1000, 60));
+        assert!(entry.is_fresh(7, 1059, 60));
+        assert!(!entry.is_fresh(7, 1060, 60));
+        assert!(!entry.is_fresh(8, 1000, 60));
+        // Clock going back doesn't make entries stale
+        assert!(entry.is_fresh(7, 900, 60));
+    }
+
+    #[test]
+    fn accounts_apart() {
+        let mut conf = config::Config::default();
+        conf.set("url", "https://cloud-api.yandex.net/v1/disk").unwrap();
+        conf.set("oauth_token", "first").unwrap();
+        let first = account(&conf).unwrap();
+        assert!(!first.contains("first"));
+        conf.set("oauth_token", "second").unwrap();
+        let second = account(&conf).unwrap();
+        assert_ne!(first, second);
+        let url = "https://cloud-api.yandex.net/v1/disk/resources?path=%2F";
+        assert_ne!(entry_path(&first, url), entry_path(&second, url));
+    }
+
+    #[test]
+    fn recent() {
+        let entry = Entry { url: "u".to_string(), revision: 7, fetched: 1000, body: String::new() };
+        assert!(entry.is_recent(1009, 10));
+        assert!(!entry.is_recent(1010, 10));
+        // Not trusted unless asked for
+        assert!(!entry.is_recent(1000, 0));
+    }
+}
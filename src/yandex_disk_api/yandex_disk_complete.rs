@@ -0,0 +1,34 @@
+use super::*;
+
+//
+// Completion of remote paths
+//
+// Completion scripts call `__complete` on every Tab, listings come from the metadata cache
+// then (see yandex_disk_cache). Tabs come in quick succession, a listing fetched moments
+// ago is taken without the revision request.
+//
+
+/// Age in seconds of listings taken without the revision request
+const TRUSTED_AGE_SECS: u64 = 30;
+
+fn listing(url: &str, conf: &config::Config, dir: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    Ok(list_dir(url, conf, dir, Some(REQUIRED_FIELDS))?
+        .into_iter()
+        .map(|x| if x.is_dir() { format!("{}/", x.name) } else { x.name })
+        .collect())
+}
+
+/// Remote paths starting with `word`
+pub fn complete(url: &str, conf: &config::Config, word: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..i + 1], &word[i + 1..]),
+        None => return Ok(Vec::new()),
+    };
+    yandex_disk_cache::trust_recent(TRUSTED_AGE_SECS);
+    let list_path = if dir.len() > 1 && !dir.ends_with(":/") { dir.trim_end_matches('/') } else { dir };
+    Ok(listing(url, conf, list_path)?
+        .into_iter()
+        .filter(|x| x.starts_with(prefix))
+        .map(|x| format!("{}{}", dir, x))
+        .collect())
+}
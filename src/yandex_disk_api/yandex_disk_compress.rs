@@ -0,0 +1,149 @@
+use std::io;
+use std::io::{Read, Write};
+
+//
+// Transparent compression
+//
+// Compressed uploads get an extension and a custom property naming the method. Both
+// are needed to decompress on download: a `.gz` uploaded as is stays as it is.
+//
+
+/// Custom property marking files compressed by the client
+pub const COMPRESSION_PROPERTY: &str = "yadisk_client_compression";
+
+/// Extensions of already compressed content, used when the config has no `compress_exclude`
+pub const DEFAULT_EXCLUDE: &[&str] = &[
+    "7z", "avi", "bz2", "gif", "gz", "heic", "jpeg", "jpg", "mkv", "mov", "mp3", "mp4", "png", "rar", "tgz",
+    "webm", "webp", "xz", "zip", "zst",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Zstd => ".zst",
+            Compression::Gzip => ".gz",
+        }
+    }
+
+    /// Compression a stored name suggests, to be confirmed by the custom property
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Compression::Zstd, Compression::Gzip].iter().copied().find(|x| name.ends_with(x.extension()))
+    }
+
+    /// Compressed content of `inner`
+    pub fn reader<R: Read + Send + 'static>(&self, inner: R) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Compression::Zstd => Box::new(zstd::stream::read::Encoder::new(inner, 0)?),
+            Compression::Gzip => Box::new(flate2::read::GzEncoder::new(inner, flate2::Compression::default())),
+        })
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Compression::Zstd),
+            "gzip" => Ok(Compression::Gzip),
+            _ => Err(format!("Unknown compression: {}", s)),
+        }
+    }
+}
+
+/// Whether a file is left uncompressed: its extension is one of `exclude` (case insensitive)
+pub fn is_excluded(name: &str, exclude: &[String]) -> bool {
+    match name.rsplit_once('.') {
+        Some((_base, ext)) => exclude.iter().any(|x| x.trim_start_matches('.').eq_ignore_ascii_case(ext)),
+        None => false,
+    }
+}
+
+/// Writer decompressing what goes through it, or passing it as is.
+/// `finish` must be called at the end.
+pub enum DecompressingWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+    Gzip(flate2::write::GzDecoder<W>),
+}
+
+impl<W: Write> DecompressingWriter<W> {
+    pub fn new(inner: W, compression: Option<Compression>) -> io::Result<Self> {
+        Ok(match compression {
+            None => DecompressingWriter::Plain(inner),
+            Some(Compression::Zstd) => DecompressingWriter::Zstd(zstd::stream::write::Decoder::new(inner)?),
+            Some(Compression::Gzip) => DecompressingWriter::Gzip(flate2::write::GzDecoder::new(inner)),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            DecompressingWriter::Plain(inner) => Ok(inner),
+            DecompressingWriter::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+            DecompressingWriter::Gzip(decoder) => decoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for DecompressingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            DecompressingWriter::Plain(inner) => inner.write(buf),
+            DecompressingWriter::Zstd(decoder) => decoder.write(buf),
+            DecompressingWriter::Gzip(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            DecompressingWriter::Plain(inner) => inner.flush(),
+            DecompressingWriter::Zstd(decoder) => decoder.flush(),
+            DecompressingWriter::Gzip(decoder) => decoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = "2020-01-01 INFO the same log line again\n".repeat(1000).into_bytes();
+        for compression in [Compression::Zstd, Compression::Gzip].iter() {
+            let mut compressed = Vec::new();
+            compression.reader(io::Cursor::new(data.clone())).unwrap().read_to_end(&mut compressed).unwrap();
+            assert!(compressed.len() < data.len() / 10, "{}", compression.as_str());
+
+            let mut writer = DecompressingWriter::new(Vec::new(), Some(*compression)).unwrap();
+            writer.write_all(&compressed).unwrap();
+            assert_eq!(writer.finish().unwrap(), data, "{}", compression.as_str());
+        }
+    }
+
+    #[test]
+    fn names() {
+        let exclude: Vec<String> = DEFAULT_EXCLUDE.iter().map(|x| x.to_string()).collect();
+        assert!(is_excluded("/photos/IMG_0001.JPG", &exclude));
+        assert!(!is_excluded("/logs/app.log", &exclude));
+        assert!(!is_excluded("Makefile", &exclude));
+        assert_eq!(Compression::from_name("app.log.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_name("app.log"), None);
+    }
+}
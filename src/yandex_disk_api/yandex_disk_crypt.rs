@@ -0,0 +1,451 @@
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{Aead, OsRng};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+//
+// Client-side encryption
+//
+// Encrypted content is a header followed by XChaCha20-Poly1305 STREAM chunks:
+//
+//   magic (8) | key kind (1) | salt (16) | stream nonce (19) | chunk ... | last chunk
+//
+// Every chunk is CHUNK_LEN bytes of plaintext plus a tag, the last one may be shorter.
+// Keys come from a passphrase (argon2id with the salt of the file) or a key file.
+//
+
+/// Appended to names of encrypted resources
+pub const ENCRYPTED_SUFFIX: &str = ".ydenc";
+/// Environment variable with the passphrase
+pub const PASSPHRASE_ENV: &str = "YADISK_PASSPHRASE";
+
+const MAGIC: &[u8; 8] = b"YDENC\x00\x00\x01";
+const KIND_PASSPHRASE: u8 = 1;
+const KIND_KEY_FILE: u8 = 2;
+const SALT_LEN: usize = 16;
+const STREAM_NONCE_LEN: usize = 19;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + STREAM_NONCE_LEN;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const CHUNK_LEN: usize = 64 * 1024;
+/// Names are encrypted with a key of their own, derived from the passphrase with this salt
+const NAME_SALT: &[u8] = b"yadisk-client file names";
+
+fn crypt_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[derive(Clone)]
+enum Secret {
+    Passphrase(String),
+    KeyFile([u8; KEY_LEN]),
+}
+
+/// Encryption key from a passphrase or a key file
+#[derive(Clone)]
+pub struct Key {
+    secret: Secret,
+    name_key: [u8; KEY_LEN],
+}
+
+fn argon2_key(passphrase: &str, salt: &[u8]) -> io::Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| crypt_error(format!("Key derivation failed: {}", e).as_str()))?;
+    Ok(key)
+}
+
+impl Key {
+    pub fn from_passphrase(passphrase: &str) -> io::Result<Self> {
+        if passphrase.is_empty() {
+            return Err(crypt_error("Empty passphrase"));
+        }
+        Ok(Key {
+            secret: Secret::Passphrase(passphrase.to_string()),
+            name_key: argon2_key(passphrase, NAME_SALT)?,
+        })
+    }
+
+    /// Key file holds 32 bytes as 64 hex digits, see `generate_key_file`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let text = text.trim();
+        if text.len() != KEY_LEN * 2 {
+            return Err(crypt_error("Key file must hold 64 hex digits"));
+        }
+        let mut key = [0u8; KEY_LEN];
+        for (i, x) in key.iter_mut().enumerate() {
+            *x = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|_| crypt_error("Key file must hold 64 hex digits"))?;
+        }
+        let mut name_key = [0u8; KEY_LEN];
+        name_key.copy_from_slice(&Sha256::new().chain_update(NAME_SALT).chain_update(key).finalize());
+        Ok(Key { secret: Secret::KeyFile(key), name_key })
+    }
+
+    fn kind(&self) -> u8 {
+        match self.secret {
+            Secret::Passphrase(_) => KIND_PASSPHRASE,
+            Secret::KeyFile(_) => KIND_KEY_FILE,
+        }
+    }
+
+    fn content_key(&self, salt: &[u8]) -> io::Result<[u8; KEY_LEN]> {
+        match &self.secret {
+            Secret::Passphrase(x) => argon2_key(x, salt),
+            Secret::KeyFile(x) => Ok(*x),
+        }
+    }
+}
+
+/// Write a new random key file, an existing file is never overwritten.
+pub fn generate_key_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let mut key = [0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    let hex: String = key.iter().map(|x| format!("{:02x}", x)).collect();
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+    writeln!(file, "{}", hex)
+}
+
+/// Size of `size` bytes of plaintext once encrypted
+pub fn encrypted_size(size: u64) -> u64 {
+    let chunks = size.div_ceil(CHUNK_LEN as u64).max(1);
+    HEADER_LEN as u64 + size + chunks * TAG_LEN as u64
+}
+
+/// Most bytes of plaintext that still fit in `size` bytes once encrypted, 0 if nothing does
+pub fn plaintext_size(size: u64) -> u64 {
+    let Some(available) = size.checked_sub(HEADER_LEN as u64) else { return 0 };
+    let sealed = (CHUNK_LEN + TAG_LEN) as u64;
+    (available / sealed) * CHUNK_LEN as u64 + (available % sealed).saturating_sub(TAG_LEN as u64)
+}
+
+//
+// Streams
+//
+
+/// Reader of encrypted content of `inner`
+pub struct EncryptingReader<R> {
+    inner: R,
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    /// Plaintext read ahead, one byte more than a chunk tells the chunk isn't the last one
+    plain: Vec<u8>,
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> EncryptingReader<R> {
+    pub fn new(inner: R, key: &Key) -> io::Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; STREAM_NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let cipher = XChaCha20Poly1305::new(&key.content_key(&salt)?.into());
+        let mut header = MAGIC.to_vec();
+        header.push(key.kind());
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce);
+
+        Ok(EncryptingReader {
+            inner,
+            encryptor: Some(EncryptorBE32::from_aead(cipher, nonce.as_ref().into())),
+            plain: Vec::with_capacity(CHUNK_LEN + 1),
+            out: header,
+            pos: 0,
+        })
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        while self.plain.len() <= CHUNK_LEN {
+            let filled = self.plain.len();
+            self.plain.resize(CHUNK_LEN + 1, 0);
+            let n = self.inner.read(&mut self.plain[filled..])?;
+            self.plain.truncate(filled + n);
+            if n == 0 {
+                break;
+            }
+        }
+
+        self.pos = 0;
+        if self.plain.len() > CHUNK_LEN {
+            let rest = self.plain.split_off(CHUNK_LEN);
+            let encryptor = self.encryptor.as_mut().unwrap();
+            self.out = encryptor.encrypt_next(self.plain.as_slice()).map_err(|_| crypt_error("Encryption failed"))?;
+            self.plain = rest;
+        } else {
+            let encryptor = self.encryptor.take().unwrap();
+            self.out = encryptor.encrypt_last(self.plain.as_slice()).map_err(|_| crypt_error("Encryption failed"))?;
+            self.plain.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for EncryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.out.len() {
+            if self.encryptor.is_none() {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+enum DecryptState {
+    /// Not enough data to tell if it's encrypted
+    Undecided,
+    Plain,
+    Encrypted(DecryptorBE32<XChaCha20Poly1305>),
+    Finished,
+}
+
+/// Writer passing plaintext through as is and decrypting encrypted content, recognized by its header.
+/// `finish` must be called at the end, the last chunk can't be told from the others before that.
+pub struct DecryptingWriter<W> {
+    inner: W,
+    key: Option<Key>,
+    state: DecryptState,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> DecryptingWriter<W> {
+    pub fn new(inner: W, key: Option<Key>) -> Self {
+        DecryptingWriter { inner, key, state: DecryptState::Undecided, buf: Vec::new() }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if self.buf.len() < MAGIC.len() {
+            return Ok(());
+        }
+        if !self.buf.starts_with(MAGIC) {
+            self.state = DecryptState::Plain;
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+            return Ok(());
+        }
+        if self.buf.len() < HEADER_LEN {
+            return Ok(());
+        }
+
+        let key = self.key.as_ref().ok_or_else(|| crypt_error("Content is encrypted, no key given"))?;
+        let kind = self.buf[MAGIC.len()];
+        if kind != key.kind() {
+            return Err(crypt_error(match kind {
+                KIND_PASSPHRASE => "Content is encrypted with a passphrase, not a key file",
+                KIND_KEY_FILE => "Content is encrypted with a key file, not a passphrase",
+                _ => "Unknown encryption key kind",
+            }));
+        }
+        let salt = &self.buf[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+        let nonce = &self.buf[MAGIC.len() + 1 + SALT_LEN..HEADER_LEN];
+        let cipher = XChaCha20Poly1305::new(&key.content_key(salt)?.into());
+        self.state = DecryptState::Encrypted(DecryptorBE32::from_aead(cipher, nonce.into()));
+        self.buf.drain(..HEADER_LEN);
+        Ok(())
+    }
+
+    /// Decrypt every buffered chunk known not to be the last one
+    fn decrypt_chunks(&mut self) -> io::Result<()> {
+        if let DecryptState::Encrypted(decryptor) = &mut self.state {
+            let mut start = 0;
+            while self.buf.len() - start > CHUNK_LEN + TAG_LEN {
+                let plain = decryptor.decrypt_next(&self.buf[start..start + CHUNK_LEN + TAG_LEN])
+                    .map_err(|_| crypt_error("Decryption failed: wrong key or damaged content"))?;
+                self.inner.write_all(&plain)?;
+                start += CHUNK_LEN + TAG_LEN;
+            }
+            self.buf.drain(..start);
+        }
+        Ok(())
+    }
+
+    /// Decrypt the last chunk, returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        match std::mem::replace(&mut self.state, DecryptState::Finished) {
+            DecryptState::Undecided if self.buf.starts_with(MAGIC) => return Err(crypt_error("Truncated encrypted content")),
+            DecryptState::Undecided => self.inner.write_all(&self.buf)?,
+            DecryptState::Encrypted(decryptor) => {
+                let plain = decryptor.decrypt_last(self.buf.as_slice())
+                    .map_err(|_| crypt_error("Decryption failed: wrong key, damaged or truncated content"))?;
+                self.inner.write_all(&plain)?;
+            }
+            DecryptState::Plain | DecryptState::Finished => (),
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for DecryptingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self.state {
+            DecryptState::Plain => return self.inner.write(data),
+            DecryptState::Finished => return Err(crypt_error("Write after finish")),
+            DecryptState::Undecided => {
+                self.buf.extend_from_slice(data);
+                self.start()?;
+            }
+            DecryptState::Encrypted(_) => self.buf.extend_from_slice(data),
+        }
+        self.decrypt_chunks()?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+//
+// Names
+//
+// Names are encrypted deterministically, the nonce is a hash of the name: the same
+// name always gives the same remote name, so overwrites and retries keep working.
+//
+
+pub fn encrypt_name(name: &str, key: &Key) -> String {
+    let digest = Sha256::new().chain_update(key.name_key).chain_update(name.as_bytes()).finalize();
+    let nonce = XNonce::from_slice(&digest[..24]);
+    let cipher = XChaCha20Poly1305::new(&key.name_key.into());
+    let mut data = nonce.to_vec();
+    data.extend(cipher.encrypt(nonce, name.as_bytes()).expect("Name encryption can't fail"));
+    format!("{}{}", base64::encode_config(data, base64::URL_SAFE_NO_PAD), ENCRYPTED_SUFFIX)
+}
+
+/// Original name of an encrypted resource: encrypted name decrypted, or the suffix removed
+pub fn decrypt_name(name: &str, key: Option<&Key>) -> String {
+    let base = match name.strip_suffix(ENCRYPTED_SUFFIX) {
+        Some(x) => x,
+        None => return name.to_string(),
+    };
+    let decrypted = key.and_then(|key| {
+        let data = base64::decode_config(base, base64::URL_SAFE_NO_PAD).ok()?;
+        if data.len() < 24 {
+            return None;
+        }
+        let cipher = XChaCha20Poly1305::new(&key.name_key.into());
+        let plain = cipher.decrypt(XNonce::from_slice(&data[..24]), &data[24..]).ok()?;
+        String::from_utf8(plain).ok()
+    });
+    decrypted.unwrap_or_else(|| base.to_string())
+}
+
+/// Hex HMAC-SHA256 of `data` with the name key, an id of content that doesn't reveal it
+pub fn keyed_hash(data: &[u8], key: &Key) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key.name_key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// Remote name of a resource uploaded encrypted
+pub fn stored_name(name: &str, key: &Key, encrypt_names: bool) -> String {
+    if encrypt_names {
+        encrypt_name(name, key)
+    } else {
+        format!("{}{}", name, ENCRYPTED_SUFFIX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key(byte: u8) -> Key {
+        let key = [byte; KEY_LEN];
+        Key { secret: Secret::KeyFile(key), name_key: key }
+    }
+
+    fn encrypt(data: &[u8], key: &Key) -> Vec<u8> {
+        let mut out = Vec::new();
+        EncryptingReader::new(data, key).unwrap().read_to_end(&mut out).unwrap();
+        out
+    }
+
+    fn decrypt(data: &[u8], key: Option<&Key>) -> io::Result<Vec<u8>> {
+        let mut writer = DecryptingWriter::new(Vec::new(), key.cloned());
+        // Odd write sizes to cross header and chunk boundaries
+        for piece in data.chunks(1000) {
+            writer.write_all(piece)?;
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = test_key(1);
+        for size in [0, 1, CHUNK_LEN - 1, CHUNK_LEN, CHUNK_LEN + 1, 3 * CHUNK_LEN + 5] {
+            let data: Vec<u8> = (0..size).map(|x| (x % 251) as u8).collect();
+            let encrypted = encrypt(&data, &key);
+            assert_eq!(encrypted.len() as u64, encrypted_size(size as u64), "size {}", size);
+            assert_eq!(decrypt(&encrypted, Some(&key)).unwrap(), data, "size {}", size);
+        }
+    }
+
+    #[test]
+    fn plaintext_fits() {
+        let sealed = (HEADER_LEN + CHUNK_LEN + TAG_LEN) as u64;
+        for size in [sealed - 1, sealed, sealed + 1, sealed + TAG_LEN as u64 + 1, 50 << 30] {
+            let plain = plaintext_size(size);
+            assert!(encrypted_size(plain) <= size, "size {}", size);
+            assert!(encrypted_size(plain + 1) > size, "size {}", size);
+        }
+        assert_eq!(plaintext_size(HEADER_LEN as u64), 0);
+    }
+
+    #[test]
+    fn keyed_hashes() {
+        assert_eq!(keyed_hash(b"data", &test_key(1)), "f9dfd03b361dcdb21e31b433104426b2faa94b8b610b251fa759683d49620be7");
+        assert_ne!(keyed_hash(b"data", &test_key(1)), keyed_hash(b"data", &test_key(2)));
+    }
+
+    #[test]
+    fn passphrase() {
+        let key = Key::from_passphrase("correct horse battery staple").unwrap();
+        let encrypted = encrypt(b"secret", &key);
+        assert_eq!(decrypt(&encrypted, Some(&key)).unwrap(), b"secret");
+        assert!(decrypt(&encrypted, Some(&test_key(1))).is_err());
+    }
+
+    #[test]
+    fn plain_content_passes_through() {
+        for data in [&b""[..], b"abc", b"plain text longer than the magic"] {
+            assert_eq!(decrypt(data, None).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn wrong_key_or_damage_fails() {
+        let encrypted = encrypt(&[7u8; CHUNK_LEN * 2], &test_key(1));
+        assert!(decrypt(&encrypted, None).is_err());
+        assert!(decrypt(&encrypted, Some(&test_key(2))).is_err());
+
+        let mut damaged = encrypted.clone();
+        damaged[HEADER_LEN + 10] ^= 1;
+        assert!(decrypt(&damaged, Some(&test_key(1))).is_err());
+        // Dropping the last chunk is detected too
+        assert!(decrypt(&encrypted[..HEADER_LEN + CHUNK_LEN + TAG_LEN], Some(&test_key(1))).is_err());
+    }
+
+    #[test]
+    fn names() {
+        let key = test_key(1);
+        let encrypted = encrypt_name("отчёт 2020.pdf", &key);
+        assert_eq!(encrypted, encrypt_name("отчёт 2020.pdf", &key));
+        assert!(encrypted.ends_with(ENCRYPTED_SUFFIX));
+        assert_eq!(decrypt_name(&encrypted, Some(&key)), "отчёт 2020.pdf");
+        assert_eq!(decrypt_name("db.sql.ydenc", Some(&key)), "db.sql");
+        assert_eq!(decrypt_name("db.sql", None), "db.sql");
+    }
+}
@@ -1,150 +1,517 @@
 //
 // Disk
 //
+// Every field the API documents as optional is an Option, and fields unknown
+// to this client are kept in `extra`, so schema drift doesn't break decoding.
+//
 
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 
+/// Fields unknown to this client
+pub type Extra = HashMap<String, serde_json::Value>;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct YaUser {
-    pub country: String,
-    pub login: String,
-    pub display_name: String,
-    pub uid: String
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct YaDisk {
-    pub unlimited_autoupload_enabled: bool,
-    pub max_file_size: u64,
-    pub total_space: u64,
-    pub trash_size: u64,
-    pub is_paid: bool,
-    pub used_space: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unlimited_autoupload_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_space: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_paid: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub used_space: Option<u64>,
+    #[serde(default)]
     pub system_folders: HashMap<String, String>,
-    pub user: YaUser,
-    pub revision: u64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<YaUser>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl YaDisk {
+    pub fn free_space(&self) -> u64 {
+        self.total_space.unwrap_or_default().saturating_sub(self.used_space.unwrap_or_default())
+    }
 }
 
 //
 // Resource
 //
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceType {
+    Dir,
+    File,
+    #[serde(other)]
+    Unknown,
+}
+
+impl fmt::Display for ResourceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            ResourceType::Dir => "dir",
+            ResourceType::File => "file",
+            ResourceType::Unknown => "unknown",
+        })
+    }
+}
+
+/// File type detected by the Disk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Audio,
+    Backup,
+    Book,
+    Compressed,
+    Data,
+    Development,
+    Diskimage,
+    Document,
+    Encoded,
+    Executable,
+    Flash,
+    Font,
+    Image,
+    Settings,
+    Spreadsheet,
+    Text,
+    Video,
+    Web,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+impl MediaType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaType::Audio => "audio",
+            MediaType::Backup => "backup",
+            MediaType::Book => "book",
+            MediaType::Compressed => "compressed",
+            MediaType::Data => "data",
+            MediaType::Development => "development",
+            MediaType::Diskimage => "diskimage",
+            MediaType::Document => "document",
+            MediaType::Encoded => "encoded",
+            MediaType::Executable => "executable",
+            MediaType::Flash => "flash",
+            MediaType::Font => "font",
+            MediaType::Image => "image",
+            MediaType::Settings => "settings",
+            MediaType::Spreadsheet => "spreadsheet",
+            MediaType::Text => "text",
+            MediaType::Video => "video",
+            MediaType::Web => "web",
+            MediaType::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl std::str::FromStr for MediaType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Items of a list page, decoded one by one: an item that can't be decoded is reported
+/// and skipped instead of failing the whole listing.
+#[derive(Serialize, Debug)]
+#[serde(transparent)]
+pub struct Items<T> {
+    pub decoded: Vec<T>,
+    /// Items the page had, skipped ones included: paging goes by this
+    #[serde(skip)]
+    pub listed: usize,
+}
+
+impl<T> Default for Items<T> {
+    fn default() -> Self {
+        Items { decoded: Vec::new(), listed: 0 }
+    }
+}
+
+/// Same decoded items, whatever was skipped
+impl<T: PartialEq> PartialEq for Items<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.decoded == other.decoded
+    }
+}
+
+impl<T> std::ops::Deref for Items<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.decoded
+    }
+}
+
+impl<T> IntoIterator for Items<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.decoded.into_iter()
+    }
+}
+
+impl<'de, T: serde::de::DeserializeOwned> Deserialize<'de> for Items<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let values: Vec<serde_json::Value> = Vec::deserialize(deserializer)?;
+        let listed = values.len();
+        let decoded = values.into_iter()
+            .filter_map(|value| {
+                let path = value.get("path").and_then(|x| x.as_str()).unwrap_or("<no path>").to_string();
+                match serde_json::from_value::<T>(value) {
+                    Ok(item) => Some(item),
+                    Err(e) => {
+                        warn!("Skipping list item {}: {}", path, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        Ok(Items { decoded, listed })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Resource {
-    #[serde(default)]
-    pub antivirus_status: String, // (undefined, optional): <Статус проверки антивирусом>,
-    #[serde(default)]
-    pub resource_id: String, // (string, optional): <Идентификатор ресурса>,
-    #[serde(default)]
-    pub share: serde_json::Value, // (ShareInfo, optional): <Информация об общей папке>,
-    #[serde(default)]
-    pub file: String, // (string, optional): <URL для скачивания файла>,
-    #[serde(default)]
-    pub size: u64, // (integer, optional): <Размер файла>,
-    #[serde(default)]
-    pub photoslice_time: String, // (string, optional): <Дата создания фото или видео файла>,
-    #[serde(default)]
-    pub _embedded: ResourceList, // (ResourceList, optional): <Список вложенных ресурсов>,
-    pub exif: Exif, // (Exif, optional): <Метаданные медиафайла (EXIF)>,
-    #[serde(default)]
-    pub custom_properties: serde_json::Value, // (object, optional): <Пользовательские атрибуты ресурса>,
-    #[serde(default)]
-    pub media_type: String, // (string, optional): <Определённый Диском тип файла>,
-    #[serde(default)]
-    pub preview: String, // (string, optional): <URL превью файла>,
-    pub r#type: String, // (string): <Тип>,
-    #[serde(default)]
-    pub mime_type: String, // (string, optional): <MIME-тип файла>,
-    #[serde(default)]
-    pub revision: u64, // (integer, optional): <Ревизия Диска в которой этот ресурс был изменён последний раз>,
-    #[serde(default)]
-    pub public_url: String, // (string, optional): <Публичный URL>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub antivirus_status: Option<serde_json::Value>, // (undefined, optional): <Статус проверки антивирусом>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_id: Option<String>, // (string, optional): <Идентификатор ресурса>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share: Option<ShareInfo>, // (ShareInfo, optional): <Информация об общей папке>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>, // (string, optional): <URL для скачивания файла>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>, // (integer, optional): <Размер файла>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub photoslice_time: Option<DateTime<FixedOffset>>, // (string, optional): <Дата создания фото или видео файла>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub _embedded: Option<ResourceList>, // (ResourceList, optional): <Список вложенных ресурсов>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exif: Option<Exif>, // (Exif, optional): <Метаданные медиафайла (EXIF)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_properties: Option<HashMap<String, serde_json::Value>>, // (object, optional): <Пользовательские атрибуты ресурса>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<MediaType>, // (string, optional): <Определённый Диском тип файла>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>, // (string, optional): <URL превью файла>,
+    pub r#type: ResourceType, // (string): <Тип>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>, // (string, optional): <MIME-тип файла>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>, // (integer, optional): <Ревизия Диска в которой этот ресурс был изменён последний раз>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>, // (string, optional): <Публичный URL>,
     pub path: String, // (string): <Путь к ресурсу>,
-    #[serde(default)]
-    pub md5: String, // (string, optional): <MD5-хэш>,
-    #[serde(default)]
-    pub public_key: String, // (string, optional): <Ключ опубликованного ресурса>,
-    #[serde(default)]
-    pub sha256: String, // (string, optional): <SHA256-хэш>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>, // (string, optional): <MD5-хэш>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>, // (string, optional): <Ключ опубликованного ресурса>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>, // (string, optional): <SHA256-хэш>,
     pub name: String, // (string): <Имя>,
-    pub created: String, // (string): <Дата создания>,
-    pub modified: String, // (string): <Дата изменения>,
-    #[serde(default)]
-    pub comment_ids: serde_json::Value // (CommentIds, optional): <Идентификаторы комментариев>
+    pub created: DateTime<FixedOffset>, // (string): <Дата создания>,
+    pub modified: DateTime<FixedOffset>, // (string): <Дата изменения>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment_ids: Option<CommentIds>, // (CommentIds, optional): <Идентификаторы комментариев>
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-impl Default for ResourceList {
-    fn default() -> Self {
-        ResourceList {
-            sort: String::from("_Uninitialized"),
-            items: Vec::new(),
-            limit: 0,
-            offset: 0,
-            path: String::from("_Uninitialized"),
-            total: 0
-        }
+impl Resource {
+    pub fn is_dir(&self) -> bool {
+        self.r#type == ResourceType::Dir
+    }
+
+    /// Size of a file, 0 when unknown
+    pub fn file_size(&self) -> u64 {
+        self.size.unwrap_or_default()
+    }
+
+    pub fn media_type(&self) -> MediaType {
+        self.media_type.unwrap_or_default()
+    }
+
+    /// Nested items of a directory, empty when not requested or not a directory
+    pub fn items(&self) -> &[Resource] {
+        self._embedded.as_ref().map_or(&[], |x| x.items.as_slice())
     }
 }
 
-/*
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ShareInfo {
-    is_root: bool, // (boolean, optional): <Признак того, что папка является корневой в группе>,
-    is_owned: bool, // (boolean, optional): <Признак, что текущий пользователь является владельцем общей папки>,
-    rights: String // (string): <Права доступа>
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_root: Option<bool>, // (boolean, optional): <Признак того, что папка является корневой в группе>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_owned: Option<bool>, // (boolean, optional): <Признак, что текущий пользователь является владельцем общей папки>,
+    pub rights: String, // (string): <Права доступа>
+    #[serde(flatten)]
+    pub extra: Extra,
 }
-*/
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
 pub struct ResourceList {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>, // (string, optional): <Поле, по которому отсортирован список>,
     #[serde(default)]
-    pub sort: String, // (string, optional): <Поле, по которому отсортирован список>,
-    pub items: Vec<Resource>, // (array[Resource]): <Элементы списка>,
-    #[serde(default)]
-    pub limit: u64, // (integer, optional): <Количество элементов на странице>,
-    #[serde(default)]
-    pub offset: u64, // (integer, optional): <Смещение от начала списка>,
-    #[serde(default)]
-    pub path: String, // (string): <Путь к ресурсу, для которого построен список>,
-    #[serde(default)]
-    pub total: u64, // (integer, optional): <Общее количество элементов в списке>
+    pub items: Items<Resource>, // (array[Resource]): <Элементы списка>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>, // (integer, optional): <Количество элементов на странице>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>, // (integer, optional): <Смещение от начала списка>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>, // (string): <Путь к ресурсу, для которого построен список>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>, // (integer, optional): <Общее количество элементов в списке>
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Exif {
-    #[serde(default)]
-    pub date_time: String, // (string, optional): <Дата съёмки.>
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_time: Option<DateTime<FixedOffset>>, // (string, optional): <Дата съёмки.>
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct CommentIds {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_resource: Option<String>, // (string, optional): <Идентификатор комментариев для приватных ресурсов.>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_resource: Option<String>, // (string, optional): <Идентификатор комментариев для публичных ресурсов.>
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+//
+// FilesResourceList
+//
+
+/// Flat list of all files, `/resources/files`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FilesResourceList {
     #[serde(default)]
-    pub private_resource: String, // (string, optional): <Идентификатор комментариев для приватных ресурсов.>,
-    #[serde(default)]
-    pub public_resource: String // (string, optional): <Идентификатор комментариев для публичных ресурсов.>
+    pub items: Items<Resource>, // (array[Resource]): <Элементы списка>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>, // (integer, optional): <Количество элементов на странице>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>, // (integer, optional): <Смещение от начала списка>
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 //
 // DownloadInfo
 //
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct DownloadInfo {
     pub href: String,
-    pub method: String,
-    pub templated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templated: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 //
 // UploadInfo
 //
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct UploadInfo {
-    pub operation_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation_id: Option<String>,
     pub href: String,
-    pub method: String,
-    pub templated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templated: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+//
+// Link
+//
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Link {
+    pub href: String, // (string): <URL>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>, // (string): <HTTP-метод>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templated: Option<bool>, // (boolean, optional): <Признак URL, который был шаблонизирован согласно RFC 6570>
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+//
+// Operation
+//
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OperationStatus {
+    Success,
+    Failed,
+    InProgress,
+    #[serde(other)]
+    Unknown,
+}
+
+impl fmt::Display for OperationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            OperationStatus::Success => "success",
+            OperationStatus::Failed => "failed",
+            OperationStatus::InProgress => "in-progress",
+            OperationStatus::Unknown => "unknown",
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Operation {
+    pub status: OperationStatus, // (string): <Статус операции>
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+
+    macro_rules! sample {
+        ($name:expr) => {
+            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/", $name))
+        };
+    }
+
+    /// Parse sample, write it back and parse again, both parsed values must be equal
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + fmt::Debug>(json: &str) -> T {
+        let parsed: T = serde_json::from_str(json).unwrap();
+        let written = serde_json::to_string(&parsed).unwrap();
+        let reparsed: T = serde_json::from_str(written.as_str()).unwrap();
+        assert_eq!(parsed, reparsed);
+        parsed
+    }
+
+    #[test]
+    fn disk() {
+        let disk: YaDisk = round_trip(sample!("disk.json"));
+        assert_eq!(disk.user.unwrap().login.as_deref(), Some("vasya.pupkin"));
+        assert_eq!(disk.system_folders["downloads"], "disk:/Загрузки/");
+    }
+
+    #[test]
+    fn directory_with_items() {
+        let dir: Resource = round_trip(sample!("resource_dir.json"));
+        assert!(dir.is_dir());
+        assert_eq!(dir.created.to_rfc3339(), "2014-04-21T14:54:42+04:00");
+        assert_eq!(dir.share.as_ref().unwrap().rights, "rw");
+        assert_eq!(dir._embedded.as_ref().unwrap().total, Some(2));
+
+        let photo = &dir.items()[0];
+        assert_eq!(photo.r#type, ResourceType::File);
+        assert_eq!(photo.media_type, Some(MediaType::Image));
+        assert_eq!(photo.exif.as_ref().unwrap().date_time.unwrap().to_rfc3339(), "2014-04-21T10:54:42+00:00");
+        assert_eq!(photo.custom_properties.as_ref().unwrap()["foo"], "1");
+        assert_eq!(photo.comment_ids.as_ref().unwrap().private_resource.as_deref(), Some("1234:abcd"));
+
+        // Media types the client doesn't know yet
+        assert_eq!(dir.items()[1].media_type(), MediaType::Unknown);
+    }
+
+    #[test]
+    fn files_list() {
+        let files: FilesResourceList = round_trip(sample!("files.json"));
+        assert_eq!(files.items.len(), 2);
+        assert_eq!(files.items[1].photoslice_time.unwrap().to_rfc3339(), "2019-07-01T12:00:00+00:00");
+    }
+
+    #[test]
+    fn links_and_operations() {
+        let link: Link = round_trip(sample!("link.json"));
+        assert_eq!(link.method.as_deref(), Some("GET"));
+        let upload: UploadInfo = round_trip(sample!("upload_info.json"));
+        assert_eq!(upload.method.as_deref(), Some("PUT"));
+        let download: DownloadInfo = round_trip(sample!("download_info.json"));
+        assert_eq!(download.templated, Some(false));
+
+        let op: Operation = round_trip(r#"{"status": "in-progress"}"#);
+        assert_eq!(op.status, OperationStatus::InProgress);
+        let op: Operation = round_trip(r#"{"status": "something-new"}"#);
+        assert_eq!(op.status, OperationStatus::Unknown);
+    }
+
+    #[test]
+    fn schema_drift() {
+        let dir: Resource = round_trip(sample!("resource_dir_drift.json"));
+        assert_eq!(dir.extra["owner"]["login"], "vasya.pupkin");
+
+        // The item with a broken date is skipped, the others are kept
+        let names: Vec<&str> = dir.items().iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["minimal.txt", "future.bin"]);
+        assert_eq!(dir._embedded.as_ref().unwrap().items.listed, 3);
+
+        let minimal = &dir.items()[0];
+        assert_eq!(minimal.exif, None);
+        assert_eq!(minimal.size, None);
+        assert_eq!(minimal.file_size(), 0);
+        assert_eq!(minimal.media_type(), MediaType::Unknown);
+
+        let future = &dir.items()[1];
+        assert_eq!(future.extra["storage_class"], "cold");
+        assert_eq!(future.exif.as_ref().unwrap().extra["gps_longitude"], 37.6);
+
+        let upload: UploadInfo = round_trip(r#"{"href": "https://uploader.disk.yandex.net/upload"}"#);
+        assert_eq!(upload.operation_id, None);
+        let download: DownloadInfo = round_trip(r#"{"href": "https://downloader.disk.yandex.ru/disk/file"}"#);
+        assert_eq!(download.method, None);
+        let disk: YaDisk = round_trip(r#"{"used_space": 10, "total_space": 100}"#);
+        assert_eq!(disk.free_space(), 90);
+    }
 }
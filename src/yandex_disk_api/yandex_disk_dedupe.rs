@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::*;
+
+//
+// Deduplicated uploads
+//
+// The public API can't take a file by its hash, but it can copy a stored file on the server.
+// An upload of content the Disk already has is a copy of the file having it then.
+//
+
+/// Attributes the index is built from
+pub const INDEX_FIELDS: &[&str] = &["path", "size", "md5", "sha256"];
+
+/// Stored files by content
+#[derive(Debug, Default)]
+pub struct ContentIndex {
+    files: HashMap<(u64, String, String), String>,
+}
+
+/// Index shared by uploads of a run, files uploaded by them are added to it
+pub type SharedIndex = Arc<Mutex<ContentIndex>>;
+
+impl ContentIndex {
+    /// Index of every file of the Disk, from the flat listing
+    pub fn fetch(url: &str, conf: &config::Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut index = ContentIndex::default();
+        for item in list_files(url, conf, None, Some(INDEX_FIELDS))? {
+            if let (Some(size), Some(md5), Some(sha256)) = (item.size, item.md5, item.sha256) {
+                index.files.insert((size, md5, sha256), item.path);
+            }
+        }
+        info!("{} stored files indexed by content", index.files.len());
+        Ok(index)
+    }
+
+    pub fn shared(self) -> SharedIndex {
+        Arc::new(Mutex::new(self))
+    }
+
+    /// Path of a stored file with this content
+    pub fn find(&self, sums: &Checksums) -> Option<&str> {
+        self.files.get(&(sums.size, sums.md5.clone(), sums.sha256.clone())).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, sums: &Checksums, path: &str) {
+        self.files.insert((sums.size, sums.md5.clone(), sums.sha256.clone()), disk_path(path));
+    }
+}
+
+/// Copy a stored file with the content of `local_path` to `remote_path`.
+/// Returns false if there is none, the file has to be uploaded then.
+pub fn upload_by_copy(
+    url: &str,
+    conf: &config::Config,
+    index: &SharedIndex,
+    local_path: &str,
+    remote_path: &str,
+    overwrite: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let sums = hash_file(local_path)?;
+    let source = match index.lock().unwrap().find(&sums) {
+        Some(x) => x.to_string(),
+        None => return Ok(false),
+    };
+    if source == disk_path(remote_path) {
+        info!("{} is already stored as {}", local_path, remote_path.bright_yellow());
+        return Ok(true);
+    }
+    info!("Copying {} to {}, same content as {}", source, remote_path.bright_yellow(), local_path);
+    copy_resource(url, conf, source.as_str(), remote_path, overwrite)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sums(size: u64, md5: &str, sha256: &str) -> Checksums {
+        Checksums { md5: md5.to_string(), sha256: sha256.to_string(), size }
+    }
+
+    #[test]
+    fn lookup() {
+        let mut index = ContentIndex::default();
+        assert_eq!(index.find(&sums(3, "a", "b")), None);
+
+        index.insert(&sums(3, "a", "b"), "/docs/x.txt");
+        assert_eq!(index.find(&sums(3, "a", "b")), Some("disk:/docs/x.txt"));
+        // All of size, md5 and sha256 must match
+        assert_eq!(index.find(&sums(4, "a", "b")), None);
+        assert_eq!(index.find(&sums(3, "c", "b")), None);
+        assert_eq!(index.find(&sums(3, "a", "c")), None);
+
+        // The latest file with the content is taken
+        index.insert(&sums(3, "a", "b"), "disk:/y.txt");
+        assert_eq!(index.find(&sums(3, "a", "b")), Some("disk:/y.txt"));
+    }
+}
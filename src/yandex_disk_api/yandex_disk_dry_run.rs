@@ -0,0 +1,396 @@
+use colored::*;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::*;
+
+//
+// Dry run
+//
+// Mutating calls check the flag where they'd act: they still resolve everything they
+// need from the Disk, print what they would create, overwrite, move or delete and
+// return. Anything not planned here is stopped by `send_request`, which refuses
+// requests other than GET in a dry run. Planning only looks things up (see `Lookup`).
+//
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Whether a request with `method` must not be sent, only GET requests are in a dry run
+pub fn refuses(method: &reqwest::Method, dry_run: bool) -> bool {
+    dry_run && method != reqwest::Method::GET
+}
+
+pub fn print_action(action: &str, path: &str, details: &str) {
+    println!("{} {:10} {} {}", "would".bright_black(), action.yellow(), path.blue(), details);
+}
+
+/// Resource at `path`, `None` if there is none
+pub fn find_resource(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+) -> Result<Option<Resource>, Box<dyn std::error::Error>> {
+    let rclient = api_client();
+    let resp = send_request(rclient, rclient.get(
+        ResourceQuery::resource(path).limit(0).fields(Some(SIZE_FIELDS)).url(url).as_str())
+        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", conf.get_str("oauth_token")?)))?;
+    match resp.status() {
+        reqwest::StatusCode::NOT_FOUND => Ok(None),
+        status if status.is_success() => Ok(Some(serde_json::from_str(resp.text()?.as_str())?)),
+        status => Err(format!("Response status is not OK: {}", status).into()),
+    }
+}
+
+/// What planning needs of the Disk, nothing that changes it
+pub trait Lookup {
+    /// Resource at `path`, `None` if there is none
+    fn find(&self, path: &str) -> Result<Option<Resource>, Box<dyn std::error::Error>>;
+    /// Files below directory `path` by relative path
+    fn files(&self, path: &str) -> Result<BTreeMap<String, Resource>, Box<dyn std::error::Error>>;
+    /// Manifest of a split file stored for `path`
+    fn manifest(&self, path: &str, options: &TransferOptions) -> Option<yandex_disk_split::SplitManifest>;
+    /// Compression of a file uploaded compressed
+    fn compression(&self, path: &str, options: &TransferOptions) -> Result<Option<Compression>, Box<dyn std::error::Error>>;
+}
+
+/// The Disk through GET requests of the API
+pub struct DiskLookup<'a> {
+    pub url: &'a str,
+    pub conf: &'a config::Config,
+}
+
+impl Lookup for DiskLookup<'_> {
+    fn find(&self, path: &str) -> Result<Option<Resource>, Box<dyn std::error::Error>> {
+        find_resource(self.url, self.conf, path)
+    }
+
+    fn files(&self, path: &str) -> Result<BTreeMap<String, Resource>, Box<dyn std::error::Error>> {
+        walk_remote(self.url, self.conf, path, Some(SIZE_FIELDS))
+    }
+
+    fn manifest(&self, path: &str, options: &TransferOptions) -> Option<yandex_disk_split::SplitManifest> {
+        yandex_disk_split::read_manifest(self.url, self.conf, path, options)
+    }
+
+    fn compression(&self, path: &str, options: &TransferOptions) -> Result<Option<Compression>, Box<dyn std::error::Error>> {
+        stored_compression(self.url, self.conf, path, options)
+    }
+}
+
+/// One line of a plan
+#[derive(Debug, PartialEq)]
+pub struct Planned {
+    pub action: &'static str,
+    pub path: String,
+    pub details: String,
+}
+
+impl Planned {
+    fn new(action: &'static str, path: &str, details: String) -> Self {
+        Planned { action, path: path.to_string(), details }
+    }
+
+    fn print(&self) {
+        print_action(self.action, self.path.as_str(), self.details.as_str());
+    }
+}
+
+/// "1.2 MiB" for files, "3 files, 4.5 MiB" for directories
+fn describe(disk: &dyn Lookup, path: &str, r: &Resource) -> Result<String, Box<dyn std::error::Error>> {
+    if !r.is_dir() {
+        return Ok(human_size(r.file_size()));
+    }
+    let files = disk.files(path)?;
+    Ok(format!("{} files, {}", files.len(), human_size(files.values().map(Resource::file_size).sum())))
+}
+
+fn existing(disk: &dyn Lookup, path: &str) -> Result<Resource, Box<dyn std::error::Error>> {
+    Ok(disk.find(path)?.ok_or_else(|| format!("{} not found", path))?)
+}
+
+fn overwrite_error(path: &str) -> Box<dyn std::error::Error> {
+    format!("{} exists, use --overwrite true to replace it", path).into()
+}
+
+fn planned_create_dir(disk: &dyn Lookup, path: &str) -> Result<Option<Planned>, Box<dyn std::error::Error>> {
+    Ok(match disk.find(path)? {
+        None => Some(Planned::new("mkdir", path, String::new())),
+        Some(_) => None,
+    })
+}
+
+fn planned_upload(
+    disk: &dyn Lookup,
+    local_path: &str,
+    remote_path: &str,
+    size: Option<u64>,
+    overwrite: bool,
+) -> Result<Planned, Box<dyn std::error::Error>> {
+    let size = size.map_or("size unknown".to_string(), human_size);
+    match disk.find(remote_path)? {
+        None => Ok(Planned::new("create", remote_path, format!("({}) from {}", size, local_path))),
+        Some(_old) if !overwrite => Err(overwrite_error(remote_path)),
+        Some(old) => Ok(Planned::new("overwrite", remote_path,
+                                     format!("({}, was {}) from {}", size, describe(disk, remote_path, &old)?, local_path))),
+    }
+}
+
+fn planned_delete(disk: &dyn Lookup, path: &str, permanently: bool) -> Result<Planned, Box<dyn std::error::Error>> {
+    let r = existing(disk, path)?;
+    let how = if permanently { "permanently" } else { "to trash" };
+    Ok(Planned::new("delete", path, format!("({}) {}", describe(disk, path, &r)?, how)))
+}
+
+fn planned_relocate(
+    disk: &dyn Lookup,
+    action: &'static str,
+    from: &str,
+    to: &str,
+    overwrite: bool,
+) -> Result<Planned, Box<dyn std::error::Error>> {
+    let source = existing(disk, from)?;
+    let details = format!("-> {} ({})", to, describe(disk, from, &source)?);
+    match disk.find(to)? {
+        None => Ok(Planned::new(action, from, details)),
+        Some(_old) if !overwrite => Err(overwrite_error(to)),
+        Some(old) => Ok(Planned::new(action, from, format!("{}, overwriting {}", details, describe(disk, to, &old)?))),
+    }
+}
+
+fn planned_download(
+    disk: &dyn Lookup,
+    path: &str,
+    target_path: Option<&str>,
+    options: &TransferOptions,
+) -> Result<Planned, Box<dyn std::error::Error>> {
+    let (size, compression) = match disk.find(path)? {
+        Some(r) if r.is_dir() => return Err(format!("{} is a directory", path).into()),
+        Some(r) => (r.file_size(), disk.compression(path, options)?),
+        // Not a file, maybe parts of a split one
+        None => match disk.manifest(path, options) {
+            Some(manifest) => (manifest.size, None),
+            None => return Err(format!("{} not found", path).into()),
+        },
+    };
+    let name = yandex_disk_crypt::decrypt_name(path.trim_end_matches('/').rsplit('/').next().unwrap_or_default(), options.key.as_ref());
+    let name = compression.and_then(|x| name.strip_suffix(x.extension())).unwrap_or(name.as_str());
+    let target = target_path.unwrap_or(name);
+    let details = if target == STDIO_PATH {
+        format!("to STDOUT ({})", human_size(size))
+    } else {
+        match std::fs::metadata(target) {
+            Ok(local) => format!("to {} ({}), overwriting local {}", target, human_size(size), human_size(local.len())),
+            Err(_) => format!("to {} ({})", target, human_size(size)),
+        }
+    };
+    Ok(Planned::new("download", path, details))
+}
+
+fn planned_publish(disk: &dyn Lookup, action: &'static str, path: &str) -> Result<Planned, Box<dyn std::error::Error>> {
+    let r = existing(disk, path)?;
+    Ok(Planned::new(action, path, format!("({})", describe(disk, path, &r)?)))
+}
+
+fn planned_import(source_url: &str, remote_path: &str) -> Planned {
+    Planned::new("import", remote_path, format!("from {}", redact_url(source_url)))
+}
+
+pub fn plan_create_dir(url: &str, conf: &config::Config, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(planned) = planned_create_dir(&DiskLookup { url, conf }, path)? {
+        planned.print();
+    }
+    Ok(())
+}
+
+/// Upload of `size` bytes (unknown for STDIN) to `remote_path`, fails like the upload would
+pub fn plan_upload(
+    url: &str,
+    conf: &config::Config,
+    local_path: &str,
+    remote_path: &str,
+    size: Option<u64>,
+    overwrite: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    planned_upload(&DiskLookup { url, conf }, local_path, remote_path, size, overwrite)?.print();
+    Ok(())
+}
+
+pub fn plan_delete(url: &str, conf: &config::Config, path: &str, permanently: bool) -> Result<(), Box<dyn std::error::Error>> {
+    planned_delete(&DiskLookup { url, conf }, path, permanently)?.print();
+    Ok(())
+}
+
+/// Server side move or copy, `action` is "move" or "copy"
+pub fn plan_relocate(
+    url: &str,
+    conf: &config::Config,
+    action: &'static str,
+    from: &str,
+    to: &str,
+    overwrite: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    planned_relocate(&DiskLookup { url, conf }, action, from, to, overwrite)?.print();
+    Ok(())
+}
+
+/// Download of `path` to `target_path`, or to a file named after it
+pub fn plan_download(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    target_path: Option<&str>,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    planned_download(&DiskLookup { url, conf }, path, target_path, options)?.print();
+    Ok(())
+}
+
+/// `action` is "publish" or "unpublish"
+pub fn plan_publish(url: &str, conf: &config::Config, action: &'static str, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    planned_publish(&DiskLookup { url, conf }, action, path)?.print();
+    Ok(())
+}
+
+pub fn plan_import(source_url: &str, remote_path: &str) {
+    planned_import(source_url, remote_path).print();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yandex_disk_test_util::{dir, file};
+
+    /// Disk with "/docs/a.txt" (3 B), "/docs/sub/b.txt" (12 B) and split "/big" (5 GiB)
+    struct FakeDisk {
+        /// Path and size of files, `None` for directories
+        entries: Vec<(&'static str, Option<u64>)>,
+    }
+
+    impl FakeDisk {
+        fn new() -> Self {
+            FakeDisk { entries: vec![("/docs", None), ("/docs/a.txt", Some(3)), ("/docs/sub", None), ("/docs/sub/b.txt", Some(12))] }
+        }
+    }
+
+    fn resource(path: &str, size: Option<u64>) -> Resource {
+        let disk_path = format!("disk:{}", path);
+        size.map_or_else(|| dir(&disk_path), |size| file(&disk_path, size, None))
+    }
+
+    impl Lookup for FakeDisk {
+        fn find(&self, path: &str) -> Result<Option<Resource>, Box<dyn std::error::Error>> {
+            Ok(self.entries.iter().find(|(x, _size)| *x == path).map(|(x, size)| resource(x, *size)))
+        }
+
+        fn files(&self, path: &str) -> Result<BTreeMap<String, Resource>, Box<dyn std::error::Error>> {
+            let prefix = format!("{}/", path);
+            Ok(self.entries.iter()
+                .filter(|(_x, size)| size.is_some())
+                .filter_map(|(x, size)| Some((x.strip_prefix(prefix.as_str())?.to_string(), resource(x, *size))))
+                .collect())
+        }
+
+        fn manifest(&self, path: &str, _options: &TransferOptions) -> Option<yandex_disk_split::SplitManifest> {
+            (path == "/big").then(|| yandex_disk_split::SplitManifest {
+                version: 1,
+                name: "big".to_string(),
+                size: 5 << 30,
+                md5: String::new(),
+                sha256: String::new(),
+                part_size: 1 << 30,
+                parts: Vec::new(),
+            })
+        }
+
+        fn compression(&self, _path: &str, _options: &TransferOptions) -> Result<Option<Compression>, Box<dyn std::error::Error>> {
+            Ok(None)
+        }
+    }
+
+    fn planned(action: &'static str, path: &str, details: &str) -> Planned {
+        Planned::new(action, path, details.to_string())
+    }
+
+    #[test]
+    fn uploads() {
+        let disk = FakeDisk::new();
+        assert_eq!(planned_upload(&disk, "new.txt", "/docs/new.txt", Some(1024), false).unwrap(),
+                   planned("create", "/docs/new.txt", "(1.0 KiB) from new.txt"));
+        assert_eq!(planned_upload(&disk, "-", "/docs/new.txt", None, false).unwrap(),
+                   planned("create", "/docs/new.txt", "(size unknown) from -"));
+        assert_eq!(planned_upload(&disk, "a.txt", "/docs/a.txt", Some(2048), true).unwrap(),
+                   planned("overwrite", "/docs/a.txt", "(2.0 KiB, was 3 B) from a.txt"));
+        assert_eq!(planned_upload(&disk, "docs.zip", "/docs", Some(20), true).unwrap(),
+                   planned("overwrite", "/docs", "(20 B, was 2 files, 15 B) from docs.zip"));
+        assert!(planned_upload(&disk, "a.txt", "/docs/a.txt", Some(2048), false).unwrap_err().to_string().contains("--overwrite"));
+
+        assert_eq!(planned_create_dir(&disk, "/new").unwrap(), Some(planned("mkdir", "/new", "")));
+        assert_eq!(planned_create_dir(&disk, "/docs").unwrap(), None);
+    }
+
+    #[test]
+    fn deletes() {
+        let disk = FakeDisk::new();
+        assert_eq!(planned_delete(&disk, "/docs", false).unwrap(), planned("delete", "/docs", "(2 files, 15 B) to trash"));
+        assert_eq!(planned_delete(&disk, "/docs/a.txt", true).unwrap(), planned("delete", "/docs/a.txt", "(3 B) permanently"));
+        assert!(planned_delete(&disk, "/missing", false).is_err());
+    }
+
+    #[test]
+    fn moves_and_copies() {
+        let disk = FakeDisk::new();
+        assert_eq!(planned_relocate(&disk, "move", "/docs/sub", "/sub", false).unwrap(),
+                   planned("move", "/docs/sub", "-> /sub (1 files, 12 B)"));
+        assert_eq!(planned_relocate(&disk, "copy", "/docs/sub/b.txt", "/docs/a.txt", true).unwrap(),
+                   planned("copy", "/docs/sub/b.txt", "-> /docs/a.txt (12 B), overwriting 3 B"));
+        assert!(planned_relocate(&disk, "copy", "/docs/sub/b.txt", "/docs/a.txt", false).is_err());
+        assert!(planned_relocate(&disk, "move", "/missing", "/x", false).is_err());
+    }
+
+    #[test]
+    fn downloads() {
+        let disk = FakeDisk::new();
+        let options = TransferOptions::default();
+        assert_eq!(planned_download(&disk, "/docs/a.txt", Some(STDIO_PATH), &options).unwrap(),
+                   planned("download", "/docs/a.txt", "to STDOUT (3 B)"));
+
+        let target = std::env::temp_dir().join(format!("yadisk-client-dry-run-{}", std::process::id()));
+        let target = target.to_str().unwrap();
+        assert_eq!(planned_download(&disk, "/big", Some(target), &options).unwrap(),
+                   planned("download", "/big", format!("to {} (5.0 GiB)", target).as_str()));
+        std::fs::write(target, "local").unwrap();
+        let existing = planned_download(&disk, "/docs/a.txt", Some(target), &options);
+        std::fs::remove_file(target).unwrap();
+        assert_eq!(existing.unwrap(), planned("download", "/docs/a.txt", format!("to {} (3 B), overwriting local 5 B", target).as_str()));
+
+        assert!(planned_download(&disk, "/docs", None, &options).is_err());
+        assert!(planned_download(&disk, "/missing", None, &options).is_err());
+    }
+
+    #[test]
+    fn publishes_and_imports() {
+        let disk = FakeDisk::new();
+        assert_eq!(planned_publish(&disk, "publish", "/docs/a.txt").unwrap(), planned("publish", "/docs/a.txt", "(3 B)"));
+        assert!(planned_publish(&disk, "unpublish", "/missing").is_err());
+        let import = planned_import("https://example.com/file.zip?token=s3cr3t", "/file.zip");
+        assert!(!import.details.contains("s3cr3t"), "{}", import.details);
+    }
+
+    #[test]
+    fn only_get_is_sent() {
+        assert!(!refuses(&reqwest::Method::GET, true));
+        for method in &[reqwest::Method::PUT, reqwest::Method::POST, reqwest::Method::DELETE, reqwest::Method::PATCH] {
+            assert!(refuses(method, true), "{}", method);
+            assert!(!refuses(method, false), "{}", method);
+        }
+    }
+}
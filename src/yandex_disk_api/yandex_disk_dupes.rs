@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use super::*;
+
+//
+// Duplicate files
+//
+
+const DUPES_FIELDS: &[&str] = &["size", "md5", "sha256"];
+
+/// Which copy of duplicate content stays, the others go to trash
+#[derive(Default)]
+pub struct KeepRule {
+    /// Keep the copy with path (without "disk:") matching the glob, before anything else
+    pub prefer: Option<glob::Pattern>,
+    /// Keep the oldest (or newest) copy by creation time
+    pub oldest: Option<bool>,
+    /// Ask which copy to keep
+    pub interactive: bool,
+}
+
+impl KeepRule {
+    pub fn is_set(&self) -> bool {
+        self.prefer.is_some() || self.oldest.is_some() || self.interactive
+    }
+
+    /// Index of the copy to keep, none when the rule can't tell
+    pub fn choose(&self, group: &[Resource]) -> Option<usize> {
+        let mut candidates: Vec<usize> = (0..group.len()).collect();
+        if let Some(pattern) = self.prefer.as_ref() {
+            let preferred: Vec<usize> = candidates.iter().copied().filter(|i| pattern.matches(group[*i].path.trim_start_matches("disk:"))).collect();
+            if !preferred.is_empty() {
+                candidates = preferred;
+            }
+        }
+        match self.oldest {
+            Some(true) => candidates.iter().copied().min_by_key(|i| group[*i].created),
+            Some(false) => candidates.iter().copied().max_by_key(|i| group[*i].created),
+            None if candidates.len() == 1 => Some(candidates[0]),
+            None => None,
+        }
+    }
+}
+
+/// Wasted space of a group: everything but one copy
+pub fn wasted(group: &[Resource]) -> u64 {
+    group.first().map_or(0, |x| x.file_size() * (group.len() as u64 - 1))
+}
+
+/// Files with the same size and checksums in groups, biggest waste first.
+/// Files without checksums (still being processed by the Disk) are left out.
+pub fn group_duplicates(files: impl IntoIterator<Item = Resource>) -> Vec<Vec<Resource>> {
+    let mut groups: HashMap<(u64, String, String), Vec<Resource>> = HashMap::new();
+    for item in files.into_iter().filter(|x| !x.is_dir()) {
+        if let (Some(size), Some(md5), Some(sha256)) = (item.size, item.md5.clone(), item.sha256.clone()) {
+            groups.entry((size, md5, sha256)).or_default().push(item);
+        }
+    }
+    let mut groups: Vec<Vec<Resource>> = groups.into_values().filter(|x| x.len() > 1).collect();
+    for group in groups.iter_mut() {
+        group.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    groups.sort_by(|a, b| wasted(b).cmp(&wasted(a)).then_with(|| a[0].path.cmp(&b[0].path)));
+    groups
+}
+
+fn ask_keeper(group: &[Resource]) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+    loop {
+        print!("Copy to keep [1-{}, empty to skip]: ", group.len());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match answer.parse::<usize>() {
+            Ok(x) if x >= 1 && x <= group.len() => return Ok(Some(x - 1)),
+            _ => println!("{}", "No such copy".red()),
+        }
+    }
+}
+
+/// Report duplicate files below `path`, and move extra copies to trash if `rule` is set.
+pub fn dupes(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    flat: bool,
+    rule: &KeepRule,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let files = if flat {
+        walk_remote_flat(url, conf, path, None, Some(DUPES_FIELDS))?
+    } else {
+        walk_remote(url, conf, path, Some(DUPES_FIELDS))?
+    };
+    let groups = group_duplicates(files.into_values());
+
+    let (mut removed, mut freed) = (0, 0);
+    for group in groups.iter() {
+        println!("{} wasted, {} copies of {}", human_size(wasted(group)).bright_red(), group.len(), human_size(group[0].file_size()));
+        for (i, item) in group.iter().enumerate() {
+            println!("  {:>3}. {}  {}", i + 1, item.created.format("%Y-%m-%d %H:%M"), item.path);
+        }
+        if !rule.is_set() {
+            continue;
+        }
+
+        let keep = match rule.choose(group) {
+            Some(x) => Some(x),
+            None if rule.interactive => ask_keeper(group)?,
+            None => {
+                warn!("Can't tell which copy of {} to keep, skipped", group[0].name);
+                None
+            }
+        };
+        if let Some(keep) = keep {
+            println!("  keeping {}", group[keep].path.bright_green());
+            for (_i, item) in group.iter().enumerate().filter(|(i, _)| *i != keep) {
+                delete_remote_file(url, conf, item.path.as_str(), false)?;
+                removed += 1;
+                freed += item.file_size();
+            }
+        }
+    }
+
+    println!("{} groups of duplicates, {} wasted", groups.len(), human_size(groups.iter().map(|x| wasted(x)).sum()));
+    if rule.is_set() {
+        println!("{} copies moved to trash, {} to free by emptying it", removed, human_size(freed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, created: &str, size: u64, md5: &str) -> Resource {
+        yandex_disk_test_util::resource(path, serde_json::json!({
+            "created": created,
+            "modified": created,
+            "size": size,
+            "md5": md5,
+            "sha256": format!("sha-{}", md5),
+        }))
+    }
+
+    #[test]
+    fn groups_and_keepers() {
+        let groups = group_duplicates(vec![
+            file("disk:/b/IMG_1.jpg", "2020-03-01T10:00:00+00:00", 100, "a"),
+            file("disk:/a/IMG_1.jpg", "2020-01-01T10:00:00+00:00", 100, "a"),
+            file("disk:/a/unique.jpg", "2020-01-01T10:00:00+00:00", 100, "b"),
+            file("disk:/x/big.zip", "2020-02-01T10:00:00+00:00", 1000, "c"),
+            file("disk:/y/big.zip", "2020-01-01T10:00:00+00:00", 1000, "c"),
+            file("disk:/z/big.zip", "2020-03-01T10:00:00+00:00", 1000, "c"),
+        ]);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(wasted(&groups[0]), 2000);
+        assert_eq!(groups[1][0].path, "disk:/a/IMG_1.jpg");
+
+        assert_eq!(KeepRule::default().choose(&groups[0]), None);
+        assert_eq!(KeepRule { oldest: Some(true), ..Default::default() }.choose(&groups[0]), Some(1));
+        assert_eq!(KeepRule { oldest: Some(false), ..Default::default() }.choose(&groups[0]), Some(2));
+        let prefer = KeepRule { prefer: Some(glob::Pattern::new("/b/*").unwrap()), ..Default::default() };
+        assert_eq!(prefer.choose(&groups[1]), Some(1));
+        assert_eq!(prefer.choose(&groups[0]), None);
+    }
+}
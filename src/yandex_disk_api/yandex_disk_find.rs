@@ -0,0 +1,325 @@
+use colored::*;
+
+use chrono::{DateTime, FixedOffset};
+use std::collections::BTreeMap;
+
+use super::*;
+
+//
+// Find remote files
+//
+
+#[derive(Default)]
+pub struct FindFilter {
+    /// Glob on resource name
+    pub name: Option<glob::Pattern>,
+    /// Regular expression on resource name
+    pub regex: Option<regex::Regex>,
+    pub resource_type: Option<ResourceType>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub created_after: Option<DateTime<FixedOffset>>,
+    pub created_before: Option<DateTime<FixedOffset>>,
+    pub modified_after: Option<DateTime<FixedOffset>>,
+    pub modified_before: Option<DateTime<FixedOffset>>,
+    /// Glob on MIME type, e.g. "image/*"
+    pub mime_type: Option<glob::Pattern>,
+    pub media_type: Option<MediaType>,
+    pub md5: Option<String>,
+}
+
+fn in_range(t: &DateTime<FixedOffset>, after: &Option<DateTime<FixedOffset>>, before: &Option<DateTime<FixedOffset>>) -> bool {
+    after.is_none_or(|x| *t >= x) && before.is_none_or(|x| *t < x)
+}
+
+impl FindFilter {
+    pub fn matches(&self, r: &Resource) -> bool {
+        self.name.as_ref().is_none_or(|x| x.matches(r.name.as_str()))
+            && self.regex.as_ref().is_none_or(|x| x.is_match(r.name.as_str()))
+            && self.resource_type.is_none_or(|x| x == r.r#type)
+            && self.min_size.is_none_or(|x| r.file_size() >= x)
+            && self.max_size.is_none_or(|x| r.file_size() <= x)
+            && in_range(&r.created, &self.created_after, &self.created_before)
+            && in_range(&r.modified, &self.modified_after, &self.modified_before)
+            && self.mime_type.as_ref().is_none_or(|x| x.matches(r.mime_type.as_deref().unwrap_or_default()))
+            && self.media_type.is_none_or(|x| x == r.media_type())
+            && self.md5.as_ref().is_none_or(|x| r.md5.as_ref().is_some_and(|md5| x.eq_ignore_ascii_case(md5)))
+    }
+
+    /// Attributes the filters look at besides the required ones
+    fn fields(&self) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.min_size.is_some() || self.max_size.is_some() {
+            fields.push("size");
+        }
+        if self.mime_type.is_some() {
+            fields.push("mime_type");
+        }
+        if self.media_type.is_some() {
+            fields.push("media_type");
+        }
+        if self.md5.is_some() {
+            fields.push("md5");
+        }
+        fields
+    }
+
+    /// Size and content filters make sense for files only
+    fn files_only(&self) -> bool {
+        self.min_size.is_some() || self.max_size.is_some() || self.mime_type.is_some()
+            || self.media_type.is_some() || self.md5.is_some()
+    }
+}
+
+/// "2020-01-31" (midnight UTC) or RFC 3339 "2020-01-31T10:00:00+03:00"
+pub fn parse_date(s: &str) -> Result<DateTime<FixedOffset>, Box<dyn std::error::Error>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t);
+    }
+    let date = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| format!("Bad date: {}", s))?;
+    Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().fixed_offset())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Paths only
+    Plain,
+    /// Type, name, size and times like `list` does
+    Long,
+    /// One JSON object per line
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(OutputFormat::Plain),
+            "long" => Ok(OutputFormat::Long),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+pub fn print_resource(r: &Resource, format: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        OutputFormat::Plain => println!("{}", r.path),
+        OutputFormat::Long => println!("({}) {:>10} {} {} {}",
+                                       r.r#type.to_string().bright_black(),
+                                       if r.is_dir() { String::new() } else { human_size(r.file_size()) },
+                                       r.modified.to_rfc3339().bright_black(),
+                                       r.media_type().as_str().bright_yellow(),
+                                       r.path.blue()),
+        OutputFormat::Json => println!("{}", serde_json::to_string(r)?),
+    }
+    Ok(())
+}
+
+/// What to do with every found resource
+pub enum FindAction {
+    Print,
+    /// Move to trash, or delete for good with `permanently`
+    Delete { permanently: bool },
+    /// Download keeping paths relative to the search root
+    Download { target_dir: String, options: TransferOptions },
+    /// Move into remote directory
+    Move { target_dir: String },
+}
+
+/// Paths of found resources by name where a name is found more than once, moving them
+/// into one folder would clash. Resources inside found folders move along with them.
+fn name_collisions<'a>(hits: &[(&'a String, &'a Resource)]) -> BTreeMap<&'a str, Vec<&'a str>> {
+    let mut names: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (rel, r) in hits.iter() {
+        let inside_found = hits.iter().any(|(other, x)| x.is_dir() && rel.starts_with(format!("{}/", other).as_str()));
+        if !inside_found {
+            names.entry(r.name.as_str()).or_default().push(r.path.as_str());
+        }
+    }
+    names.retain(|_name, paths| paths.len() > 1);
+    names
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn find(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    filter: &FindFilter,
+    flat: bool,
+    format: OutputFormat,
+    fields: Fields,
+    action: &FindAction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Request what filters and output need, JSON output has everything unless told otherwise
+    let needed: Option<Vec<&str>> = match (fields, format) {
+        (None, OutputFormat::Json) => None,
+        (None, OutputFormat::Plain) => Some(filter.fields()),
+        (None, OutputFormat::Long) => Some(filter.fields().into_iter().chain(["size", "media_type"]).collect()),
+        (Some(fields), _) => Some(filter.fields().into_iter().chain(fields.iter().copied()).collect()),
+    };
+    let fields = needed.as_deref();
+
+    let entries: BTreeMap<String, Resource> = if flat {
+        // Media type filter is done by the API here
+        walk_remote_flat(url, conf, path, filter.media_type, fields)?
+    } else {
+        walk_remote_entries(url, conf, path, None, fields)?
+    };
+
+    let files_only = filter.files_only();
+    let hits: Vec<(&String, &Resource)> = entries.iter()
+        .filter(|(_rel, r)| !(files_only && r.is_dir()))
+        .filter(|(_rel, r)| filter.matches(r))
+        .collect();
+
+    if let FindAction::Move { target_dir } = action {
+        let collisions = name_collisions(&hits);
+        if !collisions.is_empty() {
+            let names: Vec<String> = collisions.iter().map(|(name, paths)| format!("{}: {}", name, paths.join(", "))).collect();
+            return Err(format!("Found resources share names, nothing is moved into {}:\n{}", target_dir, names.join("\n")).into());
+        }
+    }
+
+    let mut attempted = 0;
+    let mut failed = 0;
+    // Directories deleted or moved along with their content
+    let mut handled_dirs: Vec<String> = Vec::new();
+    for (rel, r) in hits.iter() {
+        if handled_dirs.iter().any(|x| rel.starts_with(format!("{}/", x).as_str())) {
+            continue;
+        }
+        print_resource(r, format)?;
+        let result = match action {
+            FindAction::Print => Ok(()),
+            FindAction::Delete { permanently } => delete_remote_file(url, conf, r.path.as_str(), *permanently),
+            FindAction::Download { target_dir, options } => {
+                if r.is_dir() {
+                    continue;
+                }
+                let target = Path::new(target_dir).join(rel);
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                download_file(url, conf, r.path.as_str(), target.to_str(), options)
+            }
+            FindAction::Move { target_dir } => move_resource(url, conf, r.path.as_str(),
+                                                             format!("{}/{}", target_dir.trim_end_matches('/'), r.name).as_str(),
+                                                             false),
+        };
+        if !matches!(action, FindAction::Print) {
+            attempted += 1;
+        }
+        match result {
+            Ok(()) if r.is_dir() && matches!(action, FindAction::Delete { .. } | FindAction::Move { .. }) => {
+                handled_dirs.push(rel.to_string())
+            }
+            Ok(()) => (),
+            Err(e) => {
+                error!("{}: {}", r.path, e);
+                failed += 1;
+            }
+        }
+    }
+
+    info!("{} found", hits.len());
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} of {} actions failed", failed, attempted).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yandex_disk_test_util::{dir, file, resource};
+
+    fn photo() -> Resource {
+        resource("disk:/photos/IMG_0001.JPG", serde_json::json!({
+            "size": 3 << 20,
+            "md5": "0cc175b9c0f1b6a831c399e269772661",
+            "mime_type": "image/jpeg",
+            "media_type": "image",
+            "created": "2020-06-01T10:00:00+00:00",
+            "modified": "2021-02-01T10:00:00+00:00",
+        }))
+    }
+
+    #[test]
+    fn filters() {
+        let photo = photo();
+        assert!(FindFilter::default().matches(&photo));
+
+        let name = |x: &str| FindFilter { name: Some(glob::Pattern::new(x).unwrap()), ..Default::default() };
+        assert!(name("IMG_*").matches(&photo));
+        assert!(!name("*.png").matches(&photo));
+        let regex = FindFilter { regex: Some(regex::Regex::new(r"^IMG_\d+\.JPG$").unwrap()), ..Default::default() };
+        assert!(regex.matches(&photo));
+        assert!(!regex.matches(&file("disk:/IMG_.JPG", 1, None)));
+
+        let dirs = FindFilter { resource_type: Some(ResourceType::Dir), ..Default::default() };
+        assert!(dirs.matches(&dir("disk:/photos")));
+        assert!(!dirs.matches(&photo));
+
+        let sizes = |min, max| FindFilter { min_size: min, max_size: max, ..Default::default() };
+        assert!(sizes(Some(3 << 20), Some(3 << 20)).matches(&photo));
+        assert!(!sizes(Some((3 << 20) + 1), None).matches(&photo));
+        assert!(!sizes(None, Some((3 << 20) - 1)).matches(&photo));
+
+        // After is inclusive, before is not
+        let created = |after: &str, before: &str| FindFilter {
+            created_after: Some(parse_date(after).unwrap()),
+            created_before: Some(parse_date(before).unwrap()),
+            ..Default::default()
+        };
+        assert!(created("2020-06-01T10:00:00+00:00", "2020-06-02").matches(&photo));
+        assert!(!created("2020-01-01", "2020-06-01T10:00:00+00:00").matches(&photo));
+        let modified = FindFilter { modified_after: Some(parse_date("2021-01-01").unwrap()), ..Default::default() };
+        assert!(modified.matches(&photo));
+        assert!(!modified.matches(&file("disk:/old", 1, None)));
+
+        let mime = FindFilter { mime_type: Some(glob::Pattern::new("image/*").unwrap()), ..Default::default() };
+        assert!(mime.matches(&photo));
+        assert!(!mime.matches(&file("disk:/a.txt", 1, None)));
+        let media = FindFilter { media_type: Some(MediaType::Image), ..Default::default() };
+        assert!(media.matches(&photo));
+
+        let md5 = |x: &str| FindFilter { md5: Some(x.to_string()), ..Default::default() };
+        assert!(md5("0CC175B9C0F1B6A831C399E269772661").matches(&photo));
+        assert!(!md5("92eb5ffee6ae2fec3ad71c777531578f").matches(&photo));
+        assert!(!md5("0cc175b9c0f1b6a831c399e269772661").matches(&file("disk:/a", 1, None)));
+    }
+
+    #[test]
+    fn files_only_filters() {
+        assert!(!FindFilter::default().files_only());
+        assert!(FindFilter { min_size: Some(1), ..Default::default() }.files_only());
+        let filter = FindFilter { md5: Some("x".to_string()), mime_type: Some(glob::Pattern::new("*").unwrap()), ..Default::default() };
+        assert!(filter.files_only());
+        assert_eq!(filter.fields(), vec!["mime_type", "md5"]);
+    }
+
+    #[test]
+    fn move_collisions() {
+        let found = [file("disk:/a/x.txt", 1, None), dir("disk:/b"), file("disk:/b/x.txt", 1, None),
+                     file("disk:/c/x.txt", 1, None), file("disk:/c/y.txt", 1, None)];
+        let rels: Vec<String> = found.iter().map(|x| x.path.trim_start_matches("disk:/").to_string()).collect();
+        let hits: Vec<(&String, &Resource)> = rels.iter().zip(found.iter()).collect();
+        let collisions = name_collisions(&hits);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions["x.txt"], vec!["disk:/a/x.txt", "disk:/c/x.txt"]);
+        assert!(name_collisions(&hits[..3]).is_empty());
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("2020-01-31").unwrap().to_rfc3339(), "2020-01-31T00:00:00+00:00");
+        assert_eq!(parse_date("2020-01-31T10:00:00+03:00").unwrap().to_rfc3339(), "2020-01-31T10:00:00+03:00");
+        for bad in ["", "2020-13-01", "31.01.2020", "2020-01-31 10:00"] {
+            assert!(parse_date(bad).is_err(), "{}", bad);
+        }
+    }
+}
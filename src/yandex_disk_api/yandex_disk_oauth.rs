@@ -17,29 +17,26 @@ pub struct TokenInfo {
 }
 
 fn make_reg_user_url(conf: &config::Config) -> String {
-    String::from(format!("https://oauth.yandex.ru/authorize?response_type=code&client_id={}",
-                         conf.get_str("client_id").unwrap()))
+    format!("https://oauth.yandex.ru/authorize?response_type=code&client_id={}",
+            conf.get_str("client_id").unwrap())
 }
 
 fn get_token(conf: &config::Config, confirmation_code: &str) -> Result<TokenInfo, Box<dyn std::error::Error>> {
     let rclient = reqwest::blocking::Client::new();
-    let resp = rclient.post(format!("{}/token", YANDEX_OAUTH_URL).as_str())
+    let resp = super::send_request(&rclient, rclient.post(format!("{}/token", YANDEX_OAUTH_URL).as_str())
 //        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", encode(format!("{}:{}", CLIENT_ID, CLIENT_SECRET))))
         .form(&[("client_id", conf.get_str("client_id")?.as_str()),
                 ("client_secret", conf.get_str("client_secret")?.as_str()),
                 ("grant_type", "authorization_code"),
-                ("code", confirmation_code)])
-        .send().unwrap();
+                ("code", confirmation_code)]))?;
 // grant_type=authorization_code
 // code=confirmation_code
 
     if resp.status() == reqwest::StatusCode::OK {
-        let content: String = resp.text().unwrap();
-        println!("Response OK\n{:#?}",content);
+        let content: String = resp.text()?;
         Ok(serde_json::from_str::<TokenInfo>(content.as_str())?)
     } else {
-        println!("--Responce status is not OK\n{:#?}", resp.status());
-        Err("Responce status is not OK".to_string().into())
+        Err(format!("Response status is not OK: {}", resp.status()).into())
     }
 }
 
@@ -47,6 +44,6 @@ pub fn cli_auth_procedure(conf: &config::Config) -> Result<TokenInfo, Box<dyn st
     println!("Please proceed to :{}\nThan enter authorization code here:", make_reg_user_url(conf).bright_yellow());
     let auth_code: String = read!("{}\n");
     let t = get_token(conf, auth_code.as_str())?;
-    println!("{:#?}", t);
+    info!("Got {} token, expires in {} s", t.token_type, t.expires_in);
     Ok(t)
 }
\ No newline at end of file
@@ -0,0 +1,171 @@
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+use std::collections::BTreeSet;
+
+use super::yandex_disk_data_structures::MediaType;
+
+//
+// Resource queries
+//
+// The API returns every attribute unless `fields` lists the wanted ones, which
+// matters for big folders: previews, EXIF and download links of every item.
+//
+
+/// Resource attributes to request, `None` is all of them
+pub type Fields<'a> = Option<&'a [&'a str]>;
+
+/// Attributes a resource can't be decoded without, always requested
+pub const REQUIRED_FIELDS: &[&str] = &["name", "path", "type", "created", "modified"];
+/// Sizes only, e.g. for `du`
+pub const SIZE_FIELDS: &[&str] = &["size"];
+/// Everything integrity checks compare
+pub const CHECKSUM_FIELDS: &[&str] = &["size", "md5", "sha256"];
+/// What listing lines of `list` and `last` show besides the required attributes
+pub const LISTING_FIELDS: &[&str] = &["media_type"];
+
+/// "name,size" as given on command line
+pub fn parse_fields(s: &str) -> Vec<&str> {
+    s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()).collect()
+}
+
+/// Builder of `/resources` query URLs
+pub struct ResourceQuery {
+    endpoint: &'static str,
+    /// Where listed items are in the response
+    items_prefix: &'static str,
+    params: Vec<(&'static str, String)>,
+    fields: Option<BTreeSet<String>>,
+}
+
+impl ResourceQuery {
+    fn new(endpoint: &'static str, items_prefix: &'static str) -> Self {
+        ResourceQuery { endpoint, items_prefix, params: Vec::new(), fields: None }
+    }
+
+    /// Single resource, items of a directory come in `_embedded`
+    pub fn resource(path: &str) -> Self {
+        ResourceQuery::new("/resources", "_embedded.items.").param("path", path.to_string())
+    }
+
+    /// Flat list of all files
+    pub fn files() -> Self {
+        ResourceQuery::new("/resources/files", "items.")
+    }
+
+    pub fn last_uploaded() -> Self {
+        ResourceQuery::new("/resources/last-uploaded", "items.")
+    }
+
+    fn param(mut self, name: &'static str, value: String) -> Self {
+        self.params.push((name, value));
+        self
+    }
+
+    pub fn limit(self, limit: u64) -> Self {
+        self.param("limit", limit.to_string())
+    }
+
+    pub fn offset(self, offset: usize) -> Self {
+        self.param("offset", offset.to_string())
+    }
+
+    pub fn media_type(self, media_type: Option<MediaType>) -> Self {
+        match media_type {
+            Some(x) => self.param("media_type", x.to_string()),
+            None => self,
+        }
+    }
+
+    /// Request only these attributes of the resource itself, along with the required ones
+    pub fn fields(mut self, fields: Fields) -> Self {
+        if let Some(fields) = fields {
+            self.fields.get_or_insert_with(BTreeSet::new)
+                .extend(REQUIRED_FIELDS.iter().chain(fields).map(|x| x.to_string()));
+        }
+        self
+    }
+
+    /// Request only these attributes of listed items, along with the required ones
+    pub fn item_fields(mut self, fields: Fields) -> Self {
+        if let Some(fields) = fields {
+            let prefix = self.items_prefix;
+            let set = self.fields.get_or_insert_with(BTreeSet::new);
+            set.extend(REQUIRED_FIELDS.iter().chain(fields).map(|x| format!("{}{}", prefix, x)));
+            if self.endpoint == "/resources" {
+                // The directory itself is decoded too, and paging needs the total
+                set.extend(REQUIRED_FIELDS.iter().map(|x| x.to_string()));
+                set.insert("_embedded.total".to_string());
+            }
+        }
+        self
+    }
+
+    pub fn url(&self, base: &str) -> String {
+        let mut query: Vec<String> = self.params.iter()
+            .map(|(name, value)| format!("{}={}", name, utf8_percent_encode(value, NON_ALPHANUMERIC)))
+            .collect();
+        if let Some(fields) = self.fields.as_ref() {
+            query.push(format!("fields={}", fields.iter().cloned().collect::<Vec<String>>().join(",")));
+        }
+        if query.is_empty() {
+            format!("{}{}", base, self.endpoint)
+        } else {
+            format!("{}{}?{}", base, self.endpoint, query.join("&"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://cloud-api.yandex.net/v1/disk";
+
+    fn fields_of(url: &str) -> Vec<&str> {
+        url.split_once("fields=").map_or(Vec::new(), |(_, x)| x.split(',').collect())
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(ResourceQuery::resource("/a b/c").limit(10).offset(20).url(BASE),
+                   format!("{}/resources?path=%2Fa%20b%2Fc&limit=10&offset=20", BASE));
+        assert_eq!(ResourceQuery::files().media_type(Some(MediaType::Image)).url(BASE),
+                   format!("{}/resources/files?media_type=image", BASE));
+        assert_eq!(ResourceQuery::last_uploaded().url(BASE), format!("{}/resources/last-uploaded", BASE));
+        // All attributes unless asked otherwise
+        assert!(!ResourceQuery::resource("/").fields(None).item_fields(None).url(BASE).contains("fields="));
+    }
+
+    #[test]
+    fn fields() {
+        let url = ResourceQuery::resource("/").fields(Some(&["size", "md5", "name"])).url(BASE);
+        assert_eq!(fields_of(&url), vec!["created", "md5", "modified", "name", "path", "size", "type"]);
+
+        let url = ResourceQuery::files().item_fields(Some(&["size"])).url(BASE);
+        assert_eq!(fields_of(&url), vec!["items.created", "items.modified", "items.name", "items.path", "items.size", "items.type"]);
+    }
+
+    #[test]
+    fn item_fields_of_directory() {
+        let url = ResourceQuery::resource("/").item_fields(Some(&["md5"])).url(BASE);
+        let fields = fields_of(&url);
+        assert!(fields.contains(&"_embedded.items.md5"));
+        assert!(fields.contains(&"_embedded.total"));
+        for field in REQUIRED_FIELDS {
+            assert!(fields.contains(field), "{}", field);
+            assert!(fields.contains(&format!("_embedded.items.{}", field).as_str()), "{}", field);
+        }
+    }
+
+    #[test]
+    fn required_fields_always_requested() {
+        for url in [
+            ResourceQuery::resource("/").fields(Some(&[])).url(BASE),
+            ResourceQuery::last_uploaded().item_fields(Some(&[])).url(BASE),
+        ] {
+            let fields = fields_of(&url);
+            assert!(REQUIRED_FIELDS.iter().all(|x| fields.iter().any(|f| f.ends_with(x))), "{}", url);
+            assert_eq!(fields.len(), REQUIRED_FIELDS.len(), "{}", url);
+        }
+    }
+}
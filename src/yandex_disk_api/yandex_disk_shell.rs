@@ -0,0 +1,346 @@
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use super::*;
+
+//
+// Interactive shell
+//
+// One process for a whole session: config is read and the HTTP client is created once.
+// Directory listings are cached for completion of remote paths, commands changing
+// a directory drop its listing.
+//
+
+const HISTORY_FILE: &str = ".yadisk-client-history";
+
+const HELP: &str = "\
+cd [DIR]            change remote directory, / if not given
+pwd                 print remote directory
+ls [DIR]            list remote directory
+get REMOTE [LOCAL]  download file
+put LOCAL [REMOTE]  upload file or directory
+rm PATH             move remote file or directory to trash
+mv FROM TO          move or rename remote file or directory
+mkdir DIR           create remote directory
+lcd DIR             change local directory
+lls [DIR]           list local directory
+help                print this help
+exit                leave the shell";
+
+/// Commands whose arguments are local paths, the others take remote ones
+const LOCAL_COMMANDS: &[&str] = &["lcd", "lls"];
+
+/// Words of a command line, quotes and backslashes work as in a POSIX shell
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                let escaped = chars.next().ok_or("Line ends with a backslash")?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("Unterminated quote".to_string());
+    }
+    args.extend(word);
+    Ok(args)
+}
+
+/// Remote path of `arg` relative to `cwd`, with "." and ".." resolved
+pub fn resolve(cwd: &str, arg: &str) -> String {
+    let (prefix, path) = match arg.find(":/") {
+        Some(i) => (&arg[..i + 1], &arg[i + 1..]),
+        None if arg.starts_with('/') => ("", arg),
+        None => return resolve("/", format!("{}/{}", cwd.trim_end_matches('/'), arg).as_str()),
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("{}/{}", prefix, parts.join("/"))
+}
+
+fn parent(path: &str) -> String {
+    resolve(path, "..")
+}
+
+/// What the shell knows of a remote directory item
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+}
+
+pub struct Shell<'a> {
+    url: &'a str,
+    conf: &'a config::Config,
+    options: &'a TransferOptions,
+    cwd: RefCell<String>,
+    listings: RefCell<HashMap<String, Vec<Entry>>>,
+}
+
+impl<'a> Shell<'a> {
+    pub fn new(url: &'a str, conf: &'a config::Config, options: &'a TransferOptions) -> Self {
+        Shell { url, conf, options, cwd: RefCell::new("/".to_string()), listings: RefCell::new(HashMap::new()) }
+    }
+
+    fn path(&self, arg: &str) -> String {
+        resolve(self.cwd.borrow().as_str(), arg)
+    }
+
+    /// Items of a remote directory, fetched if not cached or `refresh` is set
+    fn listing(&self, dir: &str, refresh: bool) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+        if !refresh {
+            if let Some(items) = self.listings.borrow().get(dir) {
+                return Ok(items.clone());
+            }
+        }
+        let items: Vec<Entry> = list_dir(self.url, self.conf, dir, Some(SIZE_FIELDS))?
+            .into_iter()
+            .map(|x| Entry { is_dir: x.is_dir(), size: x.file_size(), name: x.name })
+            .collect();
+        self.listings.borrow_mut().insert(dir.to_string(), items.clone());
+        Ok(items)
+    }
+
+    fn changed(&self, path: &str) {
+        let mut listings = self.listings.borrow_mut();
+        listings.remove(parent(path).as_str());
+        listings.remove(path);
+    }
+
+    /// Run one command line, returns false on exit
+    pub fn execute(&self, line: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let args = split_args(line)?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            [] => (),
+            ["exit"] | ["quit"] => return Ok(false),
+            ["help"] => println!("{}", HELP),
+            ["pwd"] => println!("{}", self.cwd.borrow()),
+            ["cd"] => *self.cwd.borrow_mut() = "/".to_string(),
+            ["cd", dir] => {
+                let path = self.path(dir);
+                if !get_resource(self.url, self.conf, path.as_str(), Some(REQUIRED_FIELDS))?.is_dir() {
+                    return Err(format!("{} is not a directory", path).into());
+                }
+                *self.cwd.borrow_mut() = path;
+            }
+            ["ls"] => self.ls(self.cwd.borrow().clone().as_str())?,
+            ["ls", dir] => self.ls(self.path(dir).as_str())?,
+            ["get", remote] => download_file(self.url, self.conf, self.path(remote).as_str(), None, self.options)?,
+            ["get", remote, local] => download_file(self.url, self.conf, self.path(remote).as_str(), Some(local), self.options)?,
+            ["put", local] => {
+                let name = Path::new(local).file_name().ok_or("Nothing to upload")?.to_string_lossy().to_string();
+                self.put(local, self.path(name.as_str()).as_str())?;
+            }
+            ["put", local, remote] => self.put(local, self.path(remote).as_str())?,
+            ["rm", path] => {
+                let path = self.path(path);
+                delete_remote_file(self.url, self.conf, path.as_str(), false)?;
+                self.changed(path.as_str());
+            }
+            ["mv", from, to] => {
+                let (from, to) = (self.path(from), self.path(to));
+                move_resource(self.url, self.conf, from.as_str(), to.as_str(), false)?;
+                self.changed(from.as_str());
+                self.changed(to.as_str());
+            }
+            ["mkdir", dir] => {
+                let path = self.path(dir);
+                create_dir(self.url, self.conf, path.as_str())?;
+                self.changed(path.as_str());
+            }
+            ["lcd", dir] => std::env::set_current_dir(dir)?,
+            ["lls"] => lls(".")?,
+            ["lls", dir] => lls(dir)?,
+            [command, ..] => return Err(format!("Unknown command or wrong arguments: {}, see help", command).into()),
+        }
+        Ok(true)
+    }
+
+    fn ls(&self, dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+        for item in self.listing(dir, true)? {
+            if item.is_dir {
+                println!("{:>10}  {}/", "", item.name.blue());
+            } else {
+                println!("{:>10}  {}", human_size(item.size), item.name);
+            }
+        }
+        Ok(())
+    }
+
+    fn put(&self, local: &str, remote: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if Path::new(local).is_dir() {
+            upload_dir(self.url, self.conf, local, remote, self.options)?;
+        } else {
+            upload_file(self.url, self.conf, local, remote, self.options)?;
+        }
+        self.changed(remote);
+        Ok(())
+    }
+
+    /// Completions of a partial remote path
+    fn complete_remote(&self, word: &str) -> Vec<Pair> {
+        let (dir, prefix) = match word.rfind('/') {
+            Some(i) => (&word[..i + 1], &word[i + 1..]),
+            None => ("", word),
+        };
+        let items = match self.listing(self.path(dir).as_str(), false) {
+            Ok(x) => x,
+            Err(e) => {
+                debug!("No completion for {}: {}", word, e);
+                return Vec::new();
+            }
+        };
+        items
+            .iter()
+            .filter(|x| x.name.starts_with(prefix))
+            .map(|x| {
+                let suffix = if x.is_dir { "/" } else { "" };
+                Pair { display: format!("{}{}", x.name, suffix), replacement: format!("{}{}{}", dir, x.name, suffix) }
+            })
+            .collect()
+    }
+}
+
+fn lls(dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries: Vec<(String, Option<u64>)> = std::fs::read_dir(dir)?
+        .map(|entry| {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            Ok((entry.file_name().to_string_lossy().to_string(), if metadata.is_dir() { None } else { Some(metadata.len()) }))
+        })
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for (name, size) in entries {
+        match size {
+            Some(size) => println!("{:>10}  {}", human_size(size), name),
+            None => println!("{:>10}  {}/", "", name.blue()),
+        }
+    }
+    Ok(())
+}
+
+struct ShellHelper<'a> {
+    shell: &'a Shell<'a>,
+    files: FilenameCompleter,
+}
+
+impl Completer for ShellHelper<'_> {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |x| x + 1);
+        if start == 0 {
+            // The command itself
+            let commands = ["cd", "pwd", "ls", "get", "put", "rm", "mv", "mkdir", "lcd", "lls", "help", "exit"];
+            return Ok((0, commands.iter()
+                .filter(|x| x.starts_with(&line[..pos]))
+                .map(|x| Pair { display: x.to_string(), replacement: format!("{} ", x) })
+                .collect()));
+        }
+        let command = line.split_whitespace().next().unwrap_or_default();
+        let first_arg = !line[..start].trim_end().contains(char::is_whitespace);
+        if LOCAL_COMMANDS.contains(&command) || (command == "put" && first_arg) || (command == "get" && !first_arg) {
+            self.files.complete(line, pos, ctx)
+        } else {
+            Ok((start, self.shell.complete_remote(&line[start..pos])))
+        }
+    }
+}
+
+impl Hinter for ShellHelper<'_> {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper<'_> {}
+
+impl Validator for ShellHelper<'_> {}
+
+impl Helper for ShellHelper<'_> {}
+
+/// Read and run commands until exit or end of input.
+pub fn run(url: &str, conf: &config::Config, options: &TransferOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let shell = Shell::new(url, conf, options);
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ShellHelper { shell: &shell, files: FilenameCompleter::new() }));
+    let history = std::env::var("HOME").ok().map(|x| Path::new(x.as_str()).join(HISTORY_FILE));
+    if let Some(history) = history.as_ref() {
+        // There is none at the first start
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let prompt = format!("{}> ", shell.cwd.borrow());
+        match editor.readline(prompt.as_str()) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    editor.add_history_entry(line.as_str())?;
+                }
+                match shell.execute(line.as_str()) {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(e) => error!("{}", e),
+                }
+            }
+            Err(ReadlineError::Interrupted) => (),
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if let Some(history) = history.as_ref() {
+        editor.save_history(history)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args() {
+        assert_eq!(split_args("  put 'my file.txt'  \"/a b/c\" ").unwrap(), vec!["put", "my file.txt", "/a b/c"]);
+        assert_eq!(split_args(r"get a\ b ''").unwrap(), vec!["get", "a b", ""]);
+        assert!(split_args("get 'a").is_err());
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(resolve("/", "photos"), "/photos");
+        assert_eq!(resolve("/photos/2020", "../2019/./a.jpg"), "/photos/2019/a.jpg");
+        assert_eq!(resolve("/photos", "/docs/"), "/docs");
+        assert_eq!(resolve("/photos", "../.."), "/");
+        assert_eq!(resolve("/photos", "app:/backup/../x"), "app:/x");
+    }
+}
@@ -0,0 +1,199 @@
+use chrono::NaiveDateTime;
+
+use std::collections::BTreeSet;
+
+use super::*;
+
+//
+// Folder snapshots
+//
+// Every snapshot is a complete copy of a local tree in a timestamped folder under a base
+// folder. Files unchanged since the previous snapshot (same size and md5) are copied on
+// the server, only changed ones are uploaded. Old snapshots are rotated by a retention policy.
+//
+
+/// Names of snapshot folders, milliseconds keep runs in the same second apart.
+/// Names without them, as older versions made, parse too.
+pub const SNAPSHOT_FORMAT: &str = "%Y-%m-%d_%H%M%S%.3f";
+
+/// How many daily, weekly and monthly snapshots to keep, 0 is none of the kind.
+/// Nothing is rotated when all are 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Retention {
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+}
+
+impl Retention {
+    pub fn is_set(&self) -> bool {
+        self.daily > 0 || self.weekly > 0 || self.monthly > 0
+    }
+}
+
+/// Indexes of snapshots to keep: the latest one, and the latest of each of the last
+/// `daily` days (weeks, months) having snapshots.
+pub fn retained(times: &[NaiveDateTime], retention: &Retention) -> BTreeSet<usize> {
+    let mut order: Vec<usize> = (0..times.len()).collect();
+    order.sort_by(|a, b| times[*b].cmp(&times[*a]));
+
+    let mut keep: BTreeSet<usize> = order.first().copied().into_iter().collect();
+    for (count, period) in [(retention.daily, "%Y-%m-%d"), (retention.weekly, "%G-%V"), (retention.monthly, "%Y-%m")].iter() {
+        let mut periods: Vec<String> = Vec::new();
+        for i in order.iter() {
+            let key = times[*i].format(period).to_string();
+            if periods.contains(&key) {
+                continue;
+            }
+            if periods.len() >= *count {
+                break;
+            }
+            periods.push(key);
+            keep.insert(*i);
+        }
+    }
+    keep
+}
+
+/// Snapshot folders below `base` with their times, oldest first
+fn list_snapshots(
+    url: &str,
+    conf: &config::Config,
+    base: &str,
+) -> Result<Vec<(String, NaiveDateTime)>, Box<dyn std::error::Error>> {
+    let mut snapshots: Vec<(String, NaiveDateTime)> = list_dir(url, conf, base, Some(REQUIRED_FIELDS))?
+        .into_iter()
+        .filter(|x| x.is_dir())
+        .filter_map(|x| {
+            let time = NaiveDateTime::parse_from_str(x.name.as_str(), SNAPSHOT_FORMAT).ok()?;
+            Some((x.name, time))
+        })
+        .collect();
+    snapshots.sort_by_key(|x| x.1);
+    Ok(snapshots)
+}
+
+/// Upload `local_dir` into a new snapshot folder under `remote_base`, then rotate old snapshots.
+#[allow(clippy::too_many_arguments)]
+pub fn snapshot(
+    url: &str,
+    conf: &config::Config,
+    local_dir: &str,
+    remote_base: &str,
+    retention: &Retention,
+    permanently: bool,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base = remote_base.trim_end_matches('/');
+    create_dir(url, conf, base)?;
+    // A dry run only plans the base folder, there's nothing to list in a new one
+    let previous = match yandex_disk_dry_run::find_resource(url, conf, base)? {
+        Some(_) => list_snapshots(url, conf, base)?.pop(),
+        None => None,
+    };
+    let previous_files = match previous.as_ref() {
+        Some((name, _time)) => walk_remote(url, conf, format!("{}/{}", base, name).as_str(), Some(CHECKSUM_FIELDS))?,
+        None => BTreeMap::new(),
+    };
+
+    let files = walk_local_tree(Path::new(local_dir))?;
+    let mut copies: Vec<&str> = Vec::new();
+    let mut uploads: Vec<(String, u64)> = Vec::new();
+    for (rel, path) in files.iter() {
+        let size = std::fs::metadata(path)?.len();
+        // Local content is only hashed when sizes match
+        let unchanged = match previous_files.get(rel) {
+            Some(remote) if remote.size == Some(size) => remote.md5.as_deref() == Some(hash_file(path)?.md5.as_str()),
+            _ => false,
+        };
+        if unchanged {
+            copies.push(rel);
+        } else {
+            uploads.push((rel.clone(), size));
+        }
+    }
+    if options.check_quota {
+        check_upload_quota(&get_disk_info(conf)?, &uploads)?;
+    }
+
+    let name = chrono::Local::now().format(SNAPSHOT_FORMAT).to_string();
+    let dir = format!("{}/{}", base, name);
+    info!("Snapshot {}: {} files unchanged, {} to upload", dir.bright_yellow(), copies.len(), uploads.len());
+
+    // Parents go before their children in a sorted set
+    let dirs: BTreeSet<&str> = files.keys().flat_map(|rel| rel.match_indices('/').map(move |(i, _)| &rel[..i])).collect();
+    create_dir(url, conf, dir.as_str())?;
+    for sub in dirs.iter() {
+        create_dir(url, conf, format!("{}/{}", dir, sub).as_str())?;
+    }
+
+    if let Some((previous, _time)) = previous.as_ref() {
+        for rel in copies.iter() {
+            copy_resource(url, conf, format!("{}/{}/{}", base, previous, rel).as_str(), format!("{}/{}", dir, rel).as_str(), false)?;
+        }
+    }
+    let file_options = TransferOptions { check_quota: false, ..options.clone() };
+    for (rel, _size) in uploads.iter() {
+        upload_file(url, conf, files[rel].to_string_lossy().as_ref(), format!("{}/{}", dir, rel).as_str(), &file_options)?;
+    }
+    println!("Snapshot {}: {} files copied, {} uploaded ({})",
+             dir.bright_green(), copies.len(), uploads.len(), human_size(uploads.iter().map(|x| x.1).sum()));
+
+    if retention.is_set() {
+        rotate(url, conf, base, retention, permanently)?;
+    }
+    Ok(())
+}
+
+/// Delete snapshot folders under `base` the retention policy doesn't keep.
+pub fn rotate(
+    url: &str,
+    conf: &config::Config,
+    base: &str,
+    retention: &Retention,
+    permanently: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let snapshots = list_snapshots(url, conf, base)?;
+    let times: Vec<NaiveDateTime> = snapshots.iter().map(|x| x.1).collect();
+    let keep = retained(&times, retention);
+    for (i, (name, _time)) in snapshots.iter().enumerate() {
+        if !keep.contains(&i) {
+            delete_remote_file(url, conf, format!("{}/{}", base, name).as_str(), permanently)?;
+            println!("Removed snapshot {}", name.bright_yellow());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(list: &[&str]) -> Vec<NaiveDateTime> {
+        list.iter().map(|x| NaiveDateTime::parse_from_str(x, SNAPSHOT_FORMAT).unwrap()).collect()
+    }
+
+    #[test]
+    fn retention() {
+        let times = times(&[
+            "2020-09-15_030000", // monthly: September
+            "2020-10-01_030000", // weekly: week 40
+            "2020-10-10_030000", // weekly: week 41
+            "2020-10-19_030000",
+            "2020-10-20_030000", // daily
+            "2020-10-21_020000",
+            "2020-10-21_030000", // latest: daily, week 43, October
+        ]);
+        let retention = Retention { daily: 2, weekly: 3, monthly: 2 };
+        assert_eq!(retained(&times, &retention), [0, 1, 2, 4, 6].iter().copied().collect());
+        assert_eq!(retained(&times, &Retention::default()), [6].iter().copied().collect());
+        assert!(retained(&[], &retention).is_empty());
+    }
+
+    #[test]
+    fn names() {
+        let time = NaiveDateTime::parse_from_str("2020-10-21_030000.250", SNAPSHOT_FORMAT).unwrap();
+        assert_eq!(time.format(SNAPSHOT_FORMAT).to_string(), "2020-10-21_030000.250");
+        assert!(time > times(&["2020-10-21_030000"])[0]);
+    }
+}
@@ -0,0 +1,324 @@
+use serde::{Deserialize, Serialize};
+
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::*;
+
+//
+// Split uploads
+//
+// A file over the max file size of the Disk is stored as directory `<name>.ydsplit`
+// with numbered parts and a manifest. Downloads of `<name>` reassemble it.
+//
+
+pub const SPLIT_SUFFIX: &str = ".ydsplit";
+const MANIFEST_NAME: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SplitPart {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+    pub md5: String,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SplitManifest {
+    pub version: u32,
+    /// Name of the original file
+    pub name: String,
+    pub size: u64,
+    pub md5: String,
+    pub sha256: String,
+    pub part_size: u64,
+    pub parts: Vec<SplitPart>,
+}
+
+/// "/a/db.sql" and "/a/db.sql.ydsplit" are both stored in "/a/db.sql.ydsplit"
+pub fn split_dir(remote_path: &str) -> String {
+    let path = remote_path.trim_end_matches('/');
+    if path.ends_with(SPLIT_SUFFIX) {
+        path.to_string()
+    } else {
+        format!("{}{}", path, SPLIT_SUFFIX)
+    }
+}
+
+fn part_name(index: usize) -> String {
+    format!("part.{:05}", index + 1)
+}
+
+/// Offset and size of every part, an empty file still has one
+pub fn plan_parts(size: u64, part_size: u64) -> Vec<(u64, u64)> {
+    let mut parts = Vec::new();
+    let mut offset = 0;
+    loop {
+        let len = part_size.min(size - offset);
+        parts.push((offset, len));
+        offset += len;
+        if offset >= size {
+            return parts;
+        }
+    }
+}
+
+/// Explicit part size, or max file size of the Disk.
+/// Encrypted parts grow by the encryption overhead, the plaintext of a part must leave room for it.
+pub fn part_size(disk: &YaDisk, split_size: Option<u64>, encrypt: bool) -> Result<u64, Box<dyn std::error::Error>> {
+    let max = disk.max_file_size.map(|x| if encrypt { yandex_disk_crypt::plaintext_size(x) } else { x });
+    match (split_size, max) {
+        (Some(0), _) | (None, Some(0)) => Err("Part size must be positive".into()),
+        (Some(x), Some(max)) if x > max => {
+            Err(format!("Part size {} is over max file size {}", human_size(x), human_size(max)).into())
+        }
+        (Some(x), _) => Ok(x),
+        (None, Some(max)) => Ok(max),
+        (None, None) => Err("Max file size of the Disk is unknown, set part size explicitly".into()),
+    }
+}
+
+/// Planned uploads (name, stored size) for quota check, a file to be split is its parts.
+pub fn planned_parts(name: &str, size: u64, part_size: Option<u64>, encrypt: bool) -> Vec<(String, u64)> {
+    let stored = |len| if encrypt { yandex_disk_crypt::encrypted_size(len) } else { len };
+    match part_size {
+        Some(part_size) if size > part_size => plan_parts(size, part_size)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (_offset, len))| (format!("{} ({})", name, part_name(i)), stored(len)))
+            .collect(),
+        _ => vec![(name.to_string(), stored(size))],
+    }
+}
+
+fn upload_part(
+    url: &str,
+    conf: &config::Config,
+    local_path: &str,
+    remote_path: &str,
+    offset: u64,
+    size: u64,
+    options: &TransferOptions,
+) -> Result<Checksums, Box<dyn std::error::Error>> {
+    upload_verified(url, conf, remote_path, options, true, |digests| {
+        let mut file = File::open(local_path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        upload_body(file.take(size), Some(size), None, options, digests)
+    })
+}
+
+/// Upload `local_path` as parts of `part_size`, `options.parallel` at a time.
+/// The manifest goes last, so an interrupted upload never looks like a complete file.
+pub fn upload_split(
+    url: &str,
+    conf: &config::Config,
+    local_path: &str,
+    remote_path: &str,
+    part_size: u64,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let size = std::fs::metadata(local_path)?.len();
+    let plan = plan_parts(size, part_size);
+    let dir = split_dir(remote_path);
+    info!("Splitting {} into {} parts of {} in {}", local_path, plan.len(), human_size(part_size), dir.bright_yellow());
+
+    create_dir(url, conf, dir.as_str())?;
+    let whole = hash_file(local_path)?;
+
+    let parts: Mutex<Vec<Option<SplitPart>>> = Mutex::new(vec![None; plan.len()]);
+    let errors: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..options.parallel.clamp(1, plan.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(&(offset, len)) = plan.get(index) else { break };
+                    let name = part_name(index);
+                    let remote = format!("{}/{}", dir, name);
+                    match upload_part(url, conf, local_path, remote.as_str(), offset, len, options) {
+                        Ok(sums) => {
+                            debug!("{} uploaded", remote);
+                            parts.lock().unwrap()[index] = Some(SplitPart { name, offset, size: len, md5: sums.md5, sha256: sums.sha256 });
+                        }
+                        Err(e) => errors.lock().unwrap().push(format!("{}: {}", remote, e)),
+                    }
+                }
+            });
+        }
+    });
+
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        return Err(format!("{} of {} parts failed:\n  {}", errors.len(), plan.len(), errors.join("\n  ")).into());
+    }
+
+    let manifest = SplitManifest {
+        version: MANIFEST_VERSION,
+        name: Path::new(local_path).file_name().map_or(String::new(), |x| x.to_string_lossy().to_string()),
+        size: whole.size,
+        md5: whole.md5,
+        sha256: whole.sha256,
+        part_size,
+        parts: parts.into_inner().unwrap().into_iter().flatten().collect(),
+    };
+    let data = serde_json::to_vec_pretty(&manifest)?;
+    upload_verified(url, conf, format!("{}/{}", dir, MANIFEST_NAME).as_str(), options, true, |digests| {
+        upload_body(io::Cursor::new(data.clone()), Some(data.len() as u64), None, options, digests)
+    })?;
+
+    info!("Upload done: {} in {} parts", human_size(manifest.size), manifest.parts.len());
+    Ok(())
+}
+
+fn fetch_manifest(
+    url: &str,
+    conf: &config::Config,
+    manifest_path: &str,
+    options: &TransferOptions,
+) -> Result<SplitManifest, Box<dyn std::error::Error>> {
+    // Not download_to_writer: it looks for a manifest itself when there is no file
+    let (_di, mut resp) = get_download_response(url, conf, manifest_path)?;
+    let mut plain = yandex_disk_crypt::DecryptingWriter::new(Vec::new(), options.key.clone());
+    io::copy(&mut resp, &mut plain)?;
+    Ok(serde_json::from_slice(plain.finish()?.as_slice())?)
+}
+
+/// Manifest of a split file stored for `path`, if there is one.
+pub fn read_manifest(url: &str, conf: &config::Config, path: &str, options: &TransferOptions) -> Option<SplitManifest> {
+    let manifest_path = format!("{}/{}", split_dir(path), MANIFEST_NAME);
+    let result = fetch_manifest(url, conf, manifest_path.as_str(), options);
+    match result {
+        Ok(manifest) if manifest.version == MANIFEST_VERSION => Some(manifest),
+        Ok(manifest) => {
+            warn!("{}: unsupported manifest version {}", manifest_path, manifest.version);
+            None
+        }
+        Err(e) => {
+            debug!("No split manifest for {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Stream parts of a split file into `out` one after another, returns number of bytes written.
+pub fn download_split(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    manifest: &SplitManifest,
+    out: &mut dyn io::Write,
+    options: &TransferOptions,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let dir = split_dir(path);
+    info!("Reassembling {} from {} parts", manifest.name, manifest.parts.len());
+
+    let digests = SharedDigests::new();
+    let mut writer = HashingWriter::new(out, digests.clone());
+    let mut size = 0;
+    for part in manifest.parts.iter() {
+        size += download_to_writer(url, conf, format!("{}/{}", dir, part.name).as_str(), &mut writer, options)?;
+    }
+
+    if options.verify {
+        let local = digests.finish();
+        if local.size != manifest.size || local.md5 != manifest.md5 || local.sha256 != manifest.sha256 {
+            return Err(IntegrityError(format!("{}: reassembled md5 {} != {} from manifest", path, local.md5, manifest.md5)).into());
+        }
+    }
+    Ok(size)
+}
+
+/// Reassemble a split file into `target_path`, or the original file name.
+pub fn download_split_file(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    manifest: &SplitManifest,
+    target_path: Option<&str>,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The manifest comes from the Disk, only the file name of it is used
+    let name = Path::new(&manifest.name).file_name().and_then(|x| x.to_str()).unwrap_or_default();
+    let target = match target_path {
+        Some(x) => x,
+        None if name.is_empty() || name == ".." => return Err(format!("{}: bad file name {:?} in manifest", path, manifest.name).into()),
+        None => name,
+    };
+    info!("Saving as {}", target);
+
+    let mut attempt = 0;
+    loop {
+        let mut out = File::create(target)?;
+        match download_split(url, conf, path, manifest, &mut out, options) {
+            Err(e) if is_integrity_error(e.as_ref()) => {
+                std::fs::remove_file(target)?;
+                if attempt >= options.retries {
+                    return Err(e);
+                }
+                attempt += 1;
+                warn!("{}, retrying ({}/{})", e, attempt, options.retries);
+            }
+            result => return result.map(|_size| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(max_file_size: u64) -> YaDisk {
+        serde_json::from_value(serde_json::json!({ "max_file_size": max_file_size })).unwrap()
+    }
+
+    #[test]
+    fn parts() {
+        assert_eq!(plan_parts(30, 10), vec![(0, 10), (10, 10), (20, 10)]);
+        assert_eq!(plan_parts(25, 10), vec![(0, 10), (10, 10), (20, 5)]);
+        assert_eq!(plan_parts(10, 10), vec![(0, 10)]);
+        assert_eq!(plan_parts(0, 10), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn part_sizes() {
+        assert_eq!(part_size(&disk(100), None, false).unwrap(), 100);
+        assert_eq!(part_size(&disk(100), Some(40), false).unwrap(), 40);
+        assert_eq!(part_size(&disk(100), Some(100), false).unwrap(), 100);
+        assert!(part_size(&disk(100), Some(101), false).is_err());
+        assert!(part_size(&disk(100), Some(0), false).is_err());
+
+        let unknown: YaDisk = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(part_size(&unknown, Some(40), false).unwrap(), 40);
+        assert!(part_size(&unknown, None, false).is_err());
+    }
+
+    #[test]
+    fn planned() {
+        assert_eq!(planned_parts("a", 25, Some(10), false), vec![
+            ("a (part.00001)".to_string(), 10),
+            ("a (part.00002)".to_string(), 10),
+            ("a (part.00003)".to_string(), 5),
+        ]);
+        assert_eq!(planned_parts("a", 10, Some(10), false), vec![("a".to_string(), 10)]);
+        assert_eq!(planned_parts("a", 0, Some(10), false), vec![("a".to_string(), 0)]);
+        assert_eq!(planned_parts("a", 25, None, false), vec![("a".to_string(), 25)]);
+    }
+
+    #[test]
+    fn encrypted_parts_fit() {
+        let max = 1 << 30;
+        let plain = part_size(&disk(max), None, true).unwrap();
+        assert!(plain < max);
+        let parts = planned_parts("big", 5 * max, Some(plain), true);
+        assert!(parts.len() > 5);
+        assert!(parts.iter().all(|(_name, size)| *size <= max), "{:?}", parts);
+        assert_eq!(parts[0].1, yandex_disk_crypt::encrypted_size(plain));
+
+        assert!(part_size(&disk(max), Some(max), true).is_err());
+        assert_eq!(part_size(&disk(max), Some(max), false).unwrap(), max);
+    }
+}
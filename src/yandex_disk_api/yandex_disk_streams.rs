@@ -0,0 +1,135 @@
+//
+// Stream adapters used by transfers
+//
+
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checksums {
+    pub md5: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Default)]
+pub struct Digests {
+    md5: Md5,
+    sha256: Sha256,
+    size: u64,
+}
+
+impl Digests {
+    pub fn update(&mut self, data: &[u8]) {
+        self.md5.update(data);
+        self.sha256.update(data);
+        self.size += data.len() as u64;
+    }
+
+    pub fn finish(self) -> Checksums {
+        Checksums {
+            md5: format!("{:x}", self.md5.finalize()),
+            sha256: format!("{:x}", self.sha256.finalize()),
+            size: self.size,
+        }
+    }
+}
+
+/// Digests shared between a stream adapter and whoever waits for the result.
+/// Upload bodies are moved into the HTTP client, so the result can't be taken back from the reader itself.
+#[derive(Clone, Default)]
+pub struct SharedDigests(Arc<Mutex<Digests>>);
+
+impl SharedDigests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&self, data: &[u8]) {
+        self.0.lock().unwrap().update(data);
+    }
+
+    /// Result for everything passed through so far, resets the digests.
+    pub fn finish(&self) -> Checksums {
+        std::mem::take(&mut *self.0.lock().unwrap()).finish()
+    }
+}
+
+pub struct HashingReader<R> {
+    inner: R,
+    digests: SharedDigests,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, digests: SharedDigests) -> Self {
+        HashingReader { inner, digests }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.digests.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+pub struct HashingWriter<W> {
+    inner: W,
+    digests: SharedDigests,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, digests: SharedDigests) -> Self {
+        HashingWriter { inner, digests }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.digests.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bytes transferred so far, shared with whoever shows progress
+pub type Progress = Arc<AtomicU64>;
+
+/// Reader adding the number of bytes read to `progress`
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R, progress: Progress) -> Self {
+        ProgressReader { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+pub fn hash_file<P: AsRef<Path>>(path: P) -> io::Result<Checksums> {
+    let digests = SharedDigests::new();
+    let mut reader = HashingReader::new(File::open(path)?, digests.clone());
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(digests.finish())
+}
@@ -0,0 +1,26 @@
+use super::*;
+
+//
+// Resources for tests
+//
+
+/// Resource at `path` with `attributes` over the required ones of a file, e.g. `json!({"size": 10})`
+pub fn resource(path: &str, attributes: serde_json::Value) -> Resource {
+    let mut value = serde_json::json!({
+        "type": "file",
+        "path": path,
+        "name": path.trim_end_matches('/').rsplit('/').next().unwrap(),
+        "created": "2020-01-01T10:00:00+00:00",
+        "modified": "2020-01-01T10:00:00+00:00",
+    });
+    value.as_object_mut().unwrap().extend(attributes.as_object().cloned().unwrap_or_default());
+    serde_json::from_value(value).unwrap()
+}
+
+pub fn file(path: &str, size: u64, md5: Option<&str>) -> Resource {
+    resource(path, serde_json::json!({ "size": size, "md5": md5 }))
+}
+
+pub fn dir(path: &str) -> Resource {
+    resource(path, serde_json::json!({ "type": "dir" }))
+}
@@ -0,0 +1,363 @@
+use colored::*;
+
+use super::*;
+
+//
+// Transfers between accounts
+//
+// Files go from one Disk to another either as a download streamed straight into an upload,
+// or on the server side: the source file is published and the target account saves the
+// public copy to its Disk. Files the target already has with the same md5 are skipped,
+// so an interrupted transfer is resumed by running it again.
+//
+
+/// Files at least this big are copied on the server by `Method::Auto`
+pub const AUTO_PUBLISH_SIZE: u64 = 64 << 20;
+
+/// How files get to the target account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Download and upload at once, nothing is stored locally
+    Stream,
+    /// Publish the source file and save it to the target Disk, it is public meanwhile
+    Publish,
+    /// Publish big files, stream small ones
+    Auto,
+}
+
+impl std::str::FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stream" => Ok(Method::Stream),
+            "publish" => Ok(Method::Publish),
+            "auto" => Ok(Method::Auto),
+            _ => Err(format!("Unknown transfer method: {}", s)),
+        }
+    }
+}
+
+impl Method {
+    /// Publish or stream a file of `size` bytes
+    pub fn publishes(&self, size: u64) -> bool {
+        match self {
+            Method::Stream => false,
+            Method::Publish => true,
+            Method::Auto => size >= AUTO_PUBLISH_SIZE,
+        }
+    }
+}
+
+/// Config of a `[profiles.NAME]` section: the main config with the settings of the section.
+/// Listings aren't cached, the cache knows one account only.
+pub fn profile_config(settings: &config::Config, name: &str) -> Result<config::Config, Box<dyn std::error::Error>> {
+    let profile = settings.get_table(format!("profiles.{}", name).as_str())
+        .map_err(|_| format!("No profile {} in config", name))?;
+    // A profile without its own token would silently use the main account
+    if profile.get("oauth_token").and_then(|x| x.clone().into_str().ok()).is_none_or(|x| x.is_empty()) {
+        return Err(format!("No oauth_token in profile {}", name).into());
+    }
+    let mut conf = settings.clone();
+    for (key, value) in profile {
+        conf.set(key.as_str(), value)?;
+    }
+    conf.set("metadata_cache", false)?;
+    Ok(conf)
+}
+
+/// "work:/docs" is profile "work" and path "/docs"
+pub fn parse_location(spec: &str) -> Result<(&str, &str), String> {
+    match spec.split_once(':') {
+        Some((profile, path)) if !profile.is_empty() && !path.is_empty() => Ok((profile, path)),
+        _ => Err(format!("Expected PROFILE:PATH, got {}", spec)),
+    }
+}
+
+/// A path on the Disk of a profile
+pub struct Location {
+    pub profile: String,
+    pub conf: config::Config,
+    pub path: String,
+}
+
+impl Location {
+    pub fn parse(settings: &config::Config, spec: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (profile, path) = parse_location(spec)?;
+        Ok(Location { profile: profile.to_string(), conf: profile_config(settings, profile)?, path: path.to_string() })
+    }
+
+    fn url(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(self.conf.get_str("url")?)
+    }
+
+    fn join(&self, rel: &str) -> String {
+        if rel.is_empty() { self.path.clone() } else { format!("{}/{}", self.path.trim_end_matches('/'), rel) }
+    }
+}
+
+/// Checksums of a stored file, when the Disk reports all of them
+fn stored_checksums(r: &Resource) -> Option<Checksums> {
+    Some(Checksums { md5: r.md5.clone()?, sha256: r.sha256.clone()?, size: r.size? })
+}
+
+/// Target file already has the content of the source one
+pub fn same_content(source: &Resource, target: &Resource) -> bool {
+    source.size == target.size && source.md5.is_some() && source.md5 == target.md5
+}
+
+/// Stream the source file into an upload, returns checksums of the data sent
+fn stream_file(
+    from: &Location,
+    from_path: &str,
+    source: &Resource,
+    to: &Location,
+    to_path: &str,
+    overwrite: bool,
+) -> Result<Checksums, Box<dyn std::error::Error>> {
+    let (_di, resp) = get_download_response(from.url()?.as_str(), &from.conf, from_path)?;
+    let digests = SharedDigests::new();
+    let reader = HashingReader::new(resp, digests.clone());
+    let body = match source.size {
+        Some(size) => reqwest::blocking::Body::sized(reader, size),
+        None => reqwest::blocking::Body::new(reader),
+    };
+    upload_stream(to.url()?.as_str(), &to.conf, body, to_path, overwrite)?;
+    let sums = digests.finish();
+    // What was received must be what the source account has
+    compare_checksums(&sums, source)?;
+    Ok(sums)
+}
+
+/// Save a public copy of the source file to the target Disk. The source is unpublished
+/// afterwards unless it was public before.
+fn save_published(
+    from: &Location,
+    from_path: &str,
+    source: &Resource,
+    to: &Location,
+    to_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let from_url = from.url()?;
+    let public_url = match source.public_url.clone() {
+        Some(x) => x,
+        None => publish_resource(from_url.as_str(), &from.conf, from_path)?,
+    };
+    let (dir, name) = to_path.rsplit_once('/').ok_or_else(|| format!("Bad target path {}", to_path))?;
+    // Root is "/" or "disk:/"
+    let dir = if dir.is_empty() || dir.ends_with(':') { format!("{}/", dir) } else { dir.to_string() };
+    let to_url = to.url()?;
+
+    let rclient = api_client();
+    let result = send_request(rclient, rclient.post(
+        format!(
+            "{}/public/resources/save-to-disk?public_key={}&save_path={}&name={}",
+            to_url,
+            utf8_percent_encode(public_url.as_str(), NON_ALPHANUMERIC),
+            utf8_percent_encode(dir.as_str(), NON_ALPHANUMERIC),
+            utf8_percent_encode(name, NON_ALPHANUMERIC)).as_str())
+        .header(reqwest::header::AUTHORIZATION, format!("OAuth {}", to.conf.get_str("oauth_token")?)))
+        .and_then(|resp| wait_for_response(&to.conf, resp, reqwest::StatusCode::CREATED, "Save to Disk"));
+
+    if source.public_url.is_none() {
+        unpublish_resource(from_url.as_str(), &from.conf, from_path)?;
+    }
+    result
+}
+
+/// Transfer one file, repeating it up to `options.retries` times if verification fails
+#[allow(clippy::too_many_arguments)]
+fn transfer_file(
+    from: &Location,
+    from_path: &str,
+    source: &Resource,
+    to: &Location,
+    to_path: &str,
+    target_exists: bool,
+    method: Method,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let to_url = to.url()?;
+    let mut exists = target_exists;
+    let mut attempt = 0;
+    loop {
+        let result = if method.publishes(source.file_size()) {
+            // Saving never replaces a file, it picks another name
+            if exists {
+                delete_remote_file(to_url.as_str(), &to.conf, to_path, false)?;
+            }
+            save_published(from, from_path, source, to, to_path).map(|()| stored_checksums(source))
+        } else {
+            stream_file(from, from_path, source, to, to_path, exists).map(Some)
+        };
+        let result = match result {
+            Ok(Some(sums)) if options.verify => verify_remote_checksums(to_url.as_str(), &to.conf, to_path, &sums),
+            Ok(None) if options.verify => {
+                warn!("No checksums for {} on the source Disk, {} is not verified", from_path, to_path);
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if is_integrity_error(e.as_ref()) && attempt < options.retries => {
+                attempt += 1;
+                warn!("{}, retrying ({}/{})", e, attempt, options.retries);
+                // The broken copy is ours, replace it
+                exists = true;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Copy a file, or a directory with `recursive`, from one account to another.
+/// Existing target files are skipped if they have the same md5, replaced with `options.overwrite`
+/// and left alone otherwise.
+pub fn transfer(
+    from: &Location,
+    to: &Location,
+    recursive: bool,
+    method: Method,
+    options: &TransferOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let from_url = from.url()?;
+    let to_url = to.url()?;
+    let fields = Some(&["size", "md5", "sha256", "public_url"][..]);
+
+    let root = get_resource(from_url.as_str(), &from.conf, from.path.as_str(), fields)?;
+    let (sources, targets) = if root.is_dir() {
+        if !recursive {
+            return Err(format!("{}:{} is a directory, use --recursive", from.profile, from.path).into());
+        }
+        create_dir(to_url.as_str(), &to.conf, to.path.as_str())?;
+        let entries = walk_remote_entries(from_url.as_str(), &from.conf, from.path.as_str(), None, fields)?;
+        // Parents go before their children in a sorted map
+        for (rel, _item) in entries.iter().filter(|(_rel, item)| item.is_dir()) {
+            create_dir(to_url.as_str(), &to.conf, to.join(rel).as_str())?;
+        }
+        let sources: BTreeMap<String, Resource> = entries.into_iter().filter(|(_rel, item)| !item.is_dir()).collect();
+        // Not created in a dry run
+        let targets = match yandex_disk_dry_run::find_resource(to_url.as_str(), &to.conf, to.path.as_str())? {
+            Some(_dir) => walk_remote(to_url.as_str(), &to.conf, to.path.as_str(), Some(CHECKSUM_FIELDS))?,
+            None => BTreeMap::new(),
+        };
+        (sources, targets)
+    } else {
+        let mut targets = BTreeMap::new();
+        if let Ok(target) = get_resource(to_url.as_str(), &to.conf, to.path.as_str(), Some(CHECKSUM_FIELDS)) {
+            targets.insert(String::new(), target);
+        }
+        (std::iter::once((String::new(), root)).collect(), targets)
+    };
+
+    let mut planned: Vec<(&String, &Resource)> = Vec::new();
+    let mut skipped = 0;
+    for (rel, source) in sources.iter() {
+        match targets.get(rel) {
+            Some(target) if same_content(source, target) => {
+                debug!("{} is already there", to.join(rel));
+                skipped += 1;
+            }
+            Some(_target) if !options.overwrite => {
+                warn!("{} exists with other content, use --overwrite true to replace it", to.join(rel));
+                skipped += 1;
+            }
+            _ => planned.push((rel, source)),
+        }
+    }
+    if options.check_quota {
+        let files: Vec<(String, u64)> = planned.iter().map(|(rel, source)| (to.join(rel), source.file_size())).collect();
+        check_upload_quota(&get_disk_info(&to.conf)?, &files)?;
+    }
+    info!("{}:{} -> {}:{}: {} files to transfer, {} already there",
+          from.profile, from.path, to.profile, to.path.bright_yellow(), planned.len(), skipped);
+    if is_dry_run() {
+        for (rel, source) in planned.iter() {
+            let action = if targets.contains_key(*rel) { "overwrite" } else { "create" };
+            yandex_disk_dry_run::print_action(action, format!("{}:{}", to.profile, to.join(rel)).as_str(),
+                format!("({}) from {}:{}", human_size(source.file_size()), from.profile, from.join(rel)).as_str());
+        }
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    let mut transferred = 0;
+    for (rel, source) in planned.iter() {
+        let (from_path, to_path) = (from.join(rel), to.join(rel));
+        let started = Instant::now();
+        match transfer_file(from, from_path.as_str(), source, to, to_path.as_str(),
+                            targets.contains_key(*rel), method, options) {
+            Ok(()) => {
+                transferred += source.file_size();
+                println!("{:10} {} -> {} ({}, {} s)", "copied".green(), from_path, to_path.blue(),
+                         human_size(source.file_size()), started.elapsed().as_secs());
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{:10} {} -> {}: {}", "failed".red(), from_path, to_path.blue(), e);
+            }
+        }
+    }
+    println!("{} files ({}) transferred, {} skipped, {} failed",
+             planned.len() - failed, human_size(transferred), skipped, failed);
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(format!("{} of {} transfers failed, run it again to resume", failed, planned.len()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(size: u64, md5: Option<&str>) -> Resource {
+        yandex_disk_test_util::file("disk:/a", size, md5)
+    }
+
+    #[test]
+    fn locations() {
+        assert_eq!(parse_location("work:/docs"), Ok(("work", "/docs")));
+        assert_eq!(parse_location("work:disk:/docs"), Ok(("work", "disk:/docs")));
+        assert!(parse_location("/docs").is_err());
+        assert!(parse_location(":/docs").is_err());
+        assert!(parse_location("work:").is_err());
+    }
+
+    #[test]
+    fn profiles() {
+        let mut settings = config::Config::default();
+        settings.merge(config::File::from_str(r#"
+            oauth_token = "main"
+            [profiles.work]
+            oauth_token = "work"
+            [profiles.tokenless]
+            max_retries = 3
+            [profiles.empty]
+            oauth_token = ""
+        "#, config::FileFormat::Toml)).unwrap();
+        assert_eq!(profile_config(&settings, "work").unwrap().get_str("oauth_token").unwrap(), "work");
+        assert!(profile_config(&settings, "tokenless").is_err());
+        assert!(profile_config(&settings, "empty").is_err());
+        assert!(profile_config(&settings, "missing").is_err());
+    }
+
+    #[test]
+    fn content() {
+        assert!(same_content(&file(3, Some("x")), &file(3, Some("x"))));
+        assert!(!same_content(&file(3, Some("x")), &file(3, Some("y"))));
+        assert!(!same_content(&file(3, Some("x")), &file(4, Some("x"))));
+        // Unknown content is never the same
+        assert!(!same_content(&file(3, None), &file(3, None)));
+    }
+
+    #[test]
+    fn methods() {
+        assert!(!Method::Stream.publishes(AUTO_PUBLISH_SIZE));
+        assert!(Method::Publish.publishes(0));
+        assert!(Method::Auto.publishes(AUTO_PUBLISH_SIZE));
+        assert!(!Method::Auto.publishes(AUTO_PUBLISH_SIZE - 1));
+    }
+}
@@ -0,0 +1,699 @@
+use chrono::{DateTime, FixedOffset};
+use ratatui::backend::Backend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::*;
+
+//
+// Terminal file manager
+//
+// Two panes, the local directory and the Disk. Transfers are queued and run one by one
+// by a worker thread, so browsing goes on while they run. Everything the Disk is asked
+// goes through `Remote`, tests use a fake one and a headless backend.
+//
+
+const KEYS: &str = "Tab switch  Enter open  Bksp up  c copy  d delete  p publish  t transfers  r refresh  q quit";
+const REMOTE_FIELDS: &[&str] = &["size", "md5", "mime_type", "exif", "public_url"];
+
+/// Item of a directory listing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub md5: Option<String>,
+    pub mime_type: Option<String>,
+    /// Date the photo was taken, from exif
+    pub taken: Option<DateTime<FixedOffset>>,
+    pub public_url: Option<String>,
+}
+
+impl Entry {
+    fn from_resource(r: Resource) -> Self {
+        Entry {
+            is_dir: r.is_dir(),
+            size: r.file_size(),
+            md5: r.md5,
+            mime_type: r.mime_type,
+            taken: r.exif.and_then(|x| x.date_time),
+            public_url: r.public_url,
+            name: r.name,
+        }
+    }
+}
+
+/// Directories first, then by name
+fn sort_entries(entries: &mut [Entry]) {
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+}
+
+/// What the file manager needs of the Disk
+pub trait Remote: Send + Sync {
+    fn list(&self, dir: &str) -> Result<Vec<Entry>, Box<dyn std::error::Error>>;
+    /// Move to trash
+    fn delete(&self, path: &str) -> Result<(), Box<dyn std::error::Error>>;
+    /// Publish and return the public URL
+    fn publish(&self, path: &str) -> Result<String, Box<dyn std::error::Error>>;
+    fn upload(&self, local: &Path, remote: &str, progress: Progress) -> Result<(), Box<dyn std::error::Error>>;
+    fn download(&self, remote: &str, is_dir: bool, local: &Path, progress: Progress) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// The Disk through the API of this crate
+pub struct DiskRemote {
+    url: String,
+    conf: config::Config,
+    options: TransferOptions,
+}
+
+impl DiskRemote {
+    fn options(&self, progress: Progress) -> TransferOptions {
+        TransferOptions { progress: Some(progress), ..self.options.clone() }
+    }
+}
+
+impl Remote for DiskRemote {
+    fn list(&self, dir: &str) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+        Ok(list_dir(&self.url, &self.conf, dir, Some(REMOTE_FIELDS))?.into_iter().map(Entry::from_resource).collect())
+    }
+
+    fn delete(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        delete_remote_file(&self.url, &self.conf, path, false)
+    }
+
+    fn publish(&self, path: &str) -> Result<String, Box<dyn std::error::Error>> {
+        publish_resource(&self.url, &self.conf, path)
+    }
+
+    fn upload(&self, local: &Path, remote: &str, progress: Progress) -> Result<(), Box<dyn std::error::Error>> {
+        let local = local.to_string_lossy();
+        if Path::new(local.as_ref()).is_dir() {
+            upload_dir(&self.url, &self.conf, local.as_ref(), remote, &self.options(progress))
+        } else {
+            upload_file(&self.url, &self.conf, local.as_ref(), remote, &self.options(progress))
+        }
+    }
+
+    fn download(&self, remote: &str, is_dir: bool, local: &Path, progress: Progress) -> Result<(), Box<dyn std::error::Error>> {
+        let options = self.options(progress);
+        if !is_dir {
+            return download_file(&self.url, &self.conf, remote, Some(local.to_string_lossy().as_ref()), &options);
+        }
+        for rel in walk_remote(&self.url, &self.conf, remote, Some(SIZE_FIELDS))?.keys() {
+            let target = local.join(rel);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            download_file(&self.url, &self.conf, format!("{}/{}", remote, rel).as_str(),
+                          Some(target.to_string_lossy().as_ref()), &options)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransferState {
+    Queued,
+    Running,
+    Done,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub upload: bool,
+    pub from: String,
+    pub to: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub progress: Progress,
+    pub state: TransferState,
+}
+
+impl Transfer {
+    fn describe(&self) -> String {
+        let done = self.progress.load(Ordering::Relaxed);
+        let amount = if self.is_dir || self.size == 0 {
+            human_size(done)
+        } else {
+            format!("{:>3}% of {}", (done.min(self.size) * 100) / self.size, human_size(self.size))
+        };
+        let state = match &self.state {
+            TransferState::Queued => "queued".to_string(),
+            TransferState::Running => amount,
+            TransferState::Done => "done".to_string(),
+            TransferState::Failed(e) => format!("failed: {}", e),
+        };
+        format!("{} {} → {}  {}", if self.upload { "↑" } else { "↓" }, self.from, self.to, state)
+    }
+}
+
+type Transfers = Arc<Mutex<Vec<Transfer>>>;
+
+/// Run queued transfers one by one until the queue is closed
+fn transfer_worker(remote: Arc<dyn Remote>, transfers: Transfers, queue: mpsc::Receiver<usize>) {
+    for index in queue {
+        let transfer = {
+            let mut list = transfers.lock().unwrap();
+            list[index].state = TransferState::Running;
+            list[index].clone()
+        };
+        let result = if transfer.upload {
+            remote.upload(Path::new(&transfer.from), &transfer.to, transfer.progress.clone())
+        } else {
+            remote.download(&transfer.from, transfer.is_dir, Path::new(&transfer.to), transfer.progress.clone())
+        };
+        transfers.lock().unwrap()[index].state = match result {
+            Ok(()) => TransferState::Done,
+            Err(e) => TransferState::Failed(e.to_string()),
+        };
+    }
+}
+
+fn join_remote(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+fn parent_remote(dir: &str) -> String {
+    match dir.trim_end_matches('/').rsplit_once('/') {
+        Some((parent, _)) if parent.is_empty() || parent.ends_with(':') => format!("{}/", parent),
+        Some((parent, _)) => parent.to_string(),
+        None => dir.to_string(),
+    }
+}
+
+struct Pane {
+    local: bool,
+    cwd: String,
+    entries: Vec<Entry>,
+    state: ListState,
+}
+
+impl Pane {
+    fn selected(&self) -> Option<&Entry> {
+        self.state.selected().and_then(|i| self.entries.get(i))
+    }
+
+    fn path_of(&self, name: &str) -> String {
+        if self.local {
+            Path::new(&self.cwd).join(name).to_string_lossy().to_string()
+        } else {
+            join_remote(&self.cwd, name)
+        }
+    }
+
+    fn parent(&self) -> String {
+        if self.local {
+            Path::new(&self.cwd).parent().map_or(self.cwd.clone(), |x| x.to_string_lossy().to_string())
+        } else {
+            parent_remote(&self.cwd)
+        }
+    }
+}
+
+fn list_local(dir: &str) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        entries.push(Entry {
+            name: entry.file_name().to_string_lossy().to_string(),
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            ..Default::default()
+        });
+    }
+    Ok(entries)
+}
+
+pub struct App {
+    remote: Arc<dyn Remote>,
+    panes: [Pane; 2],
+    /// 0 is the local pane, 1 the Disk
+    focus: usize,
+    transfers: Transfers,
+    queue: mpsc::Sender<usize>,
+    finished: usize,
+    show_transfers: bool,
+    status: String,
+    /// Remote path waiting for delete confirmation
+    confirm_delete: Option<String>,
+    /// Text to put into the clipboard of the terminal
+    pub clipboard: Option<String>,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new(remote: Arc<dyn Remote>, local_dir: &str, remote_dir: &str) -> Self {
+        let transfers: Transfers = Arc::new(Mutex::new(Vec::new()));
+        let (queue, jobs) = mpsc::channel();
+        let (worker_remote, worker_transfers) = (remote.clone(), transfers.clone());
+        std::thread::spawn(move || transfer_worker(worker_remote, worker_transfers, jobs));
+
+        let pane = |local: bool, cwd: &str| Pane { local, cwd: cwd.to_string(), entries: Vec::new(), state: ListState::default() };
+        let mut app = App {
+            remote,
+            panes: [pane(true, local_dir), pane(false, remote_dir)],
+            focus: 0,
+            transfers,
+            queue,
+            finished: 0,
+            show_transfers: true,
+            status: String::new(),
+            confirm_delete: None,
+            clipboard: None,
+            quit: false,
+        };
+        app.reload(0);
+        app.reload(1);
+        app
+    }
+
+    /// List the directory of a pane again, keeping selection where possible
+    fn list(&self, side: usize, dir: &str) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+        let mut entries = if self.panes[side].local { list_local(dir)? } else { self.remote.list(dir)? };
+        sort_entries(&mut entries);
+        Ok(entries)
+    }
+
+    fn reload(&mut self, side: usize) {
+        let result = self.list(side, &self.panes[side].cwd);
+        let pane = &mut self.panes[side];
+        match result {
+            Ok(entries) => {
+                let selected = pane.state.selected().unwrap_or(0).min(entries.len().saturating_sub(1));
+                pane.state.select(if entries.is_empty() { None } else { Some(selected) });
+                pane.entries = entries;
+            }
+            Err(e) => self.status = format!("{}: {}", pane.cwd, e),
+        }
+    }
+
+    /// Go to `dir`, the pane stays where it was if `dir` can't be listed
+    fn change_dir(&mut self, dir: String) {
+        match self.list(self.focus, &dir) {
+            Ok(entries) => {
+                let pane = &mut self.panes[self.focus];
+                pane.state.select(if entries.is_empty() { None } else { Some(0) });
+                pane.entries = entries;
+                pane.cwd = dir;
+            }
+            Err(e) => self.status = format!("{}: {}", dir, e),
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let pane = &mut self.panes[self.focus];
+        if pane.entries.is_empty() {
+            return;
+        }
+        let current = pane.state.selected().unwrap_or(0) as isize;
+        pane.state.select(Some((current + delta).clamp(0, pane.entries.len() as isize - 1) as usize));
+    }
+
+    /// Queue a copy of the selected item into the directory of the other pane
+    fn copy_selected(&mut self) {
+        let (from, to) = (&self.panes[self.focus], &self.panes[1 - self.focus]);
+        let entry = match from.selected() {
+            Some(x) => x.clone(),
+            None => return,
+        };
+        let transfer = Transfer {
+            upload: from.local,
+            from: from.path_of(&entry.name),
+            to: to.path_of(&entry.name),
+            is_dir: entry.is_dir,
+            size: entry.size,
+            progress: Arc::new(AtomicU64::new(0)),
+            state: TransferState::Queued,
+        };
+        self.status = format!("Queued {}", transfer.from);
+        let index = {
+            let mut list = self.transfers.lock().unwrap();
+            list.push(transfer);
+            list.len() - 1
+        };
+        if self.queue.send(index).is_err() {
+            self.status = "Transfers stopped".to_string();
+        }
+    }
+
+    fn publish_selected(&mut self) {
+        let pane = &self.panes[self.focus];
+        let path = match (pane.local, pane.selected()) {
+            (false, Some(entry)) => pane.path_of(&entry.name),
+            _ => return,
+        };
+        match self.remote.publish(&path) {
+            Ok(link) => {
+                self.status = format!("{} copied", link);
+                self.clipboard = Some(link);
+                self.reload(1);
+            }
+            Err(e) => self.status = format!("Can't publish {}: {}", path, e),
+        }
+    }
+
+    /// Refresh panes when transfers have finished
+    pub fn tick(&mut self) {
+        let finished = self.transfers.lock().unwrap().iter()
+            .filter(|x| matches!(x.state, TransferState::Done | TransferState::Failed(_)))
+            .count();
+        if finished != self.finished {
+            self.finished = finished;
+            self.reload(0);
+            self.reload(1);
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode) {
+        if let Some(path) = self.confirm_delete.take() {
+            self.status = match key {
+                KeyCode::Char('y') => match self.remote.delete(&path) {
+                    Ok(()) => format!("{} moved to trash", path),
+                    Err(e) => format!("Can't delete {}: {}", path, e),
+                },
+                _ => "Cancelled".to_string(),
+            };
+            self.reload(1);
+            return;
+        }
+
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab => self.focus = 1 - self.focus,
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Home => self.move_selection(isize::MIN / 2),
+            KeyCode::End => self.move_selection(isize::MAX / 2),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => {
+                let pane = &self.panes[self.focus];
+                if let Some(dir) = pane.selected().filter(|x| x.is_dir).map(|x| pane.path_of(&x.name)) {
+                    self.change_dir(dir);
+                }
+            }
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => {
+                let parent = self.panes[self.focus].parent();
+                self.change_dir(parent);
+            }
+            KeyCode::Char('c') | KeyCode::F(5) => self.copy_selected(),
+            KeyCode::Char('d') | KeyCode::Delete | KeyCode::F(8) => {
+                let pane = &self.panes[self.focus];
+                let path = pane.selected().map(|x| pane.path_of(&x.name));
+                match (pane.local, path) {
+                    (true, _) => self.status = "Local files are not deleted here".to_string(),
+                    (false, Some(path)) => {
+                        self.status = format!("Move {} to trash? y/n", path);
+                        self.confirm_delete = Some(path);
+                    }
+                    (false, None) => (),
+                }
+            }
+            KeyCode::Char('p') => self.publish_selected(),
+            KeyCode::Char('t') => self.show_transfers = !self.show_transfers,
+            KeyCode::Char('r') => {
+                self.reload(0);
+                self.reload(1);
+            }
+            _ => (),
+        }
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(5),
+                Constraint::Length(7),
+                Constraint::Length(if self.show_transfers { 8 } else { 0 }),
+                Constraint::Length(1),
+            ])
+            .split(frame.area());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(rows[0]);
+
+        for side in 0..2 {
+            self.draw_pane(frame, side, columns[side]);
+        }
+        self.draw_preview(frame, rows[1]);
+        if self.show_transfers {
+            self.draw_transfers(frame, rows[2]);
+        }
+        let status = if self.status.is_empty() { KEYS } else { self.status.as_str() };
+        frame.render_widget(Paragraph::new(status), rows[3]);
+    }
+
+    fn draw_pane(&mut self, frame: &mut Frame, side: usize, area: Rect) {
+        let focused = side == self.focus;
+        let pane = &mut self.panes[side];
+        let width = area.width.saturating_sub(4) as usize;
+        let items: Vec<ListItem> = pane.entries.iter()
+            .map(|x| {
+                if x.is_dir {
+                    ListItem::new(Line::from(Span::styled(format!("{}/", x.name), Style::default().fg(Color::Blue))))
+                } else {
+                    let size = human_size(x.size);
+                    let name_width = width.saturating_sub(size.chars().count() + 1);
+                    let name: String = x.name.chars().take(name_width).collect();
+                    ListItem::new(format!("{:<w$} {}", name, size, w = name_width))
+                }
+            })
+            .collect();
+        let title = format!(" {}: {} ", if pane.local { "Local" } else { "Disk" }, pane.cwd);
+        let border = if focused { Style::default().fg(Color::Yellow) } else { Style::default() };
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title).border_style(border))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, area, &mut pane.state);
+    }
+
+    fn draw_preview(&self, frame: &mut Frame, area: Rect) {
+        let pane = &self.panes[self.focus];
+        let lines: Vec<Line> = match pane.selected() {
+            Some(entry) => {
+                let mut lines = vec![Line::from(pane.path_of(&entry.name))];
+                if !entry.is_dir {
+                    lines.push(Line::from(format!("Size: {} ({} bytes)", human_size(entry.size), entry.size)));
+                }
+                let optional = [
+                    ("MD5", entry.md5.clone()),
+                    ("MIME", entry.mime_type.clone()),
+                    ("Taken", entry.taken.map(|x| x.format("%Y-%m-%d %H:%M:%S").to_string())),
+                    ("Public", entry.public_url.clone()),
+                ];
+                lines.extend(optional.iter().filter_map(|(name, value)| Some(Line::from(format!("{}: {}", name, value.as_ref()?)))));
+                lines
+            }
+            None => Vec::new(),
+        };
+        frame.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Info ")), area);
+    }
+
+    fn draw_transfers(&self, frame: &mut Frame, area: Rect) {
+        let transfers = self.transfers.lock().unwrap();
+        // The latest ones that fit
+        let items: Vec<ListItem> = transfers.iter().rev()
+            .take(area.height.saturating_sub(2) as usize)
+            .map(|x| {
+                let style = match x.state {
+                    TransferState::Failed(_) => Style::default().fg(Color::Red),
+                    TransferState::Done => Style::default().fg(Color::DarkGray),
+                    _ => Style::default(),
+                };
+                ListItem::new(Span::styled(x.describe(), style))
+            })
+            .collect();
+        let title = format!(" Transfers: {} of {} finished ", self.finished, transfers.len());
+        frame.render_widget(List::new(items).block(Block::default().borders(Borders::ALL).title(title)), area);
+    }
+}
+
+/// Put text into the clipboard of the terminal with an OSC 52 sequence
+fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64::encode(text))?;
+    stdout.flush()
+}
+
+fn event_loop<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
+    while !app.quit {
+        app.tick();
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Some(text) = app.clipboard.take() {
+            copy_to_clipboard(&text)?;
+        }
+        if event::poll(Duration::from_millis(200))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key.code);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the file manager in the current directory and the root of the Disk.
+pub fn run(url: &str, conf: &config::Config, options: &TransferOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let remote = Arc::new(DiskRemote { url: url.to_string(), conf: conf.clone(), options: options.clone() });
+    let mut app = App::new(remote, std::env::current_dir()?.to_string_lossy().as_ref(), "/");
+
+    // Log records would break the screen, errors are shown in the status line
+    let level = log::max_level();
+    log::set_max_level(log::LevelFilter::Off);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    log::set_max_level(level);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    #[derive(Default)]
+    struct FakeRemote {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl Remote for FakeRemote {
+        fn list(&self, dir: &str) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+            let file = |name: &str, size| Entry { name: name.to_string(), size, ..Default::default() };
+            Ok(match dir {
+                "/" => vec![
+                    Entry { name: "photos".to_string(), is_dir: true, ..Default::default() },
+                    Entry { md5: Some("0cc175b9c0f1b6a831c399e269772661".to_string()), ..file("notes.txt", 2048) },
+                ],
+                "/photos" => vec![file("IMG_0001.jpg", 3 * 1024 * 1024)],
+                _ => return Err(format!("{} not found", dir).into()),
+            })
+        }
+
+        fn delete(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+            self.calls.lock().unwrap().push(format!("delete {}", path));
+            Ok(())
+        }
+
+        fn publish(&self, path: &str) -> Result<String, Box<dyn std::error::Error>> {
+            self.calls.lock().unwrap().push(format!("publish {}", path));
+            Ok("https://yadi.sk/d/abc".to_string())
+        }
+
+        fn upload(&self, local: &Path, remote: &str, progress: Progress) -> Result<(), Box<dyn std::error::Error>> {
+            progress.fetch_add(std::fs::metadata(local)?.len(), Ordering::Relaxed);
+            self.calls.lock().unwrap().push(format!("upload {} {}", local.display(), remote));
+            Ok(())
+        }
+
+        fn download(&self, remote: &str, _is_dir: bool, local: &Path, _progress: Progress) -> Result<(), Box<dyn std::error::Error>> {
+            self.calls.lock().unwrap().push(format!("download {} {}", remote, local.display()));
+            Ok(())
+        }
+    }
+
+    fn local_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("yadisk-client-tui-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("report.pdf"), vec![0u8; 1500]).unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    fn screen(app: &mut App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn browse_and_preview() {
+        let remote = Arc::new(FakeRemote::default());
+        let mut app = App::new(remote, &local_dir("browse"), "/");
+        let text = screen(&mut app);
+        assert!(text.contains("report.pdf"), "{}", text);
+        assert!(text.contains("Disk: /"), "{}", text);
+        assert!(text.contains("photos/"), "{}", text);
+
+        app.handle_key(KeyCode::Tab);
+        app.handle_key(KeyCode::Down);
+        let text = screen(&mut app);
+        assert!(text.contains("MD5: 0cc175b9c0f1b6a831c399e269772661"), "{}", text);
+        assert!(text.contains("Size: 2.0 KiB"), "{}", text);
+
+        app.handle_key(KeyCode::Up);
+        app.handle_key(KeyCode::Enter);
+        assert!(screen(&mut app).contains("IMG_0001.jpg"));
+        app.handle_key(KeyCode::Backspace);
+        assert!(screen(&mut app).contains("notes.txt"));
+    }
+
+    #[test]
+    fn transfers_delete_publish() {
+        let remote = Arc::new(FakeRemote::default());
+        let local = local_dir("transfers");
+        let mut app = App::new(remote.clone(), &local, "/");
+
+        app.handle_key(KeyCode::Char('c'));
+        for _ in 0..100 {
+            app.tick();
+            if app.finished == 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(screen(&mut app).contains("Transfers: 1 of 1 finished"));
+
+        app.handle_key(KeyCode::Tab);
+        app.handle_key(KeyCode::Down);
+        app.handle_key(KeyCode::Char('d'));
+        app.handle_key(KeyCode::Char('y'));
+        app.handle_key(KeyCode::Char('p'));
+        assert_eq!(app.clipboard.as_deref(), Some("https://yadi.sk/d/abc"));
+        assert_eq!(*remote.calls.lock().unwrap(), vec![
+            format!("upload {} /report.pdf", Path::new(&local).join("report.pdf").display()),
+            "delete /notes.txt".to_string(),
+            "publish /notes.txt".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn failed_change_dir_stays() {
+        let remote = Arc::new(FakeRemote::default());
+        let mut app = App::new(remote, &local_dir("change_dir"), "/");
+        app.handle_key(KeyCode::Tab);
+        app.handle_key(KeyCode::Down);
+        app.change_dir("/missing".to_string());
+        assert_eq!(app.panes[1].cwd, "/");
+        assert_eq!(app.panes[1].state.selected(), Some(1));
+        assert!(app.status.contains("/missing not found"), "{}", app.status);
+        assert!(screen(&mut app).contains("Disk: /"));
+    }
+
+    #[test]
+    fn remote_parents() {
+        assert_eq!(parent_remote("/photos/2020"), "/photos");
+        assert_eq!(parent_remote("/photos"), "/");
+        assert_eq!(parent_remote("/"), "/");
+        assert_eq!(parent_remote("app:/backup"), "app:/");
+    }
+}
@@ -0,0 +1,205 @@
+use colored::*;
+
+use std::collections::BTreeMap;
+
+use super::*;
+
+//
+// Disk usage and tree view of remote folders
+//
+
+#[derive(Default)]
+struct Node {
+    is_dir: bool,
+    /// File size, or total size of everything inside for directories
+    size: u64,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn dir() -> Self {
+        Node { is_dir: true, ..Default::default() }
+    }
+}
+
+/// Build tree from entries keyed by relative path. Flat listings have no directory
+/// entries, so directories are also created from file paths.
+fn build_tree(entries: &BTreeMap<String, Resource>) -> Node {
+    let mut root = Node::dir();
+    for (rel, item) in entries.iter() {
+        let is_dir = item.is_dir();
+        let size = if is_dir { 0 } else { item.file_size() };
+
+        let mut node = &mut root;
+        node.size += size;
+        for component in rel.split('/') {
+            node = node.children.entry(component.to_string()).or_insert_with(Node::dir);
+            node.size += size;
+        }
+        node.is_dir = is_dir;
+    }
+    root
+}
+
+fn fetch_entries(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    max_depth: Option<usize>,
+    flat: bool,
+) -> Result<BTreeMap<String, Resource>, Box<dyn std::error::Error>> {
+    if flat {
+        walk_remote_flat(url, conf, path, None, Some(SIZE_FIELDS))
+    } else {
+        walk_remote_entries(url, conf, path, max_depth, Some(SIZE_FIELDS))
+    }
+}
+
+fn format_size(size: u64, bytes: bool) -> String {
+    if bytes { size.to_string() } else { human_size(size) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuSort {
+    Size,
+    Name,
+}
+
+impl std::str::FromStr for DuSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(DuSort::Size),
+            "name" => Ok(DuSort::Name),
+            _ => Err(format!("Unknown sort order: {}", s)),
+        }
+    }
+}
+
+fn collect_dirs<'a>(node: &'a Node, rel: String, depth: usize, max_depth: Option<usize>, out: &mut Vec<(String, &'a Node)>) {
+    if max_depth.is_some_and(|x| depth > x) {
+        return;
+    }
+    for (name, child) in node.children.iter().filter(|(_name, child)| child.is_dir) {
+        let child_rel = if rel.is_empty() { name.clone() } else { format!("{}/{}", rel, name) };
+        collect_dirs(child, child_rel, depth + 1, max_depth, out);
+    }
+    out.push((rel, node));
+}
+
+/// Print total size of every folder below `path` like `du` does.
+pub fn disk_usage(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    max_depth: Option<usize>,
+    sort: DuSort,
+    bytes: bool,
+    flat: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Sizes of deep folders add up to the shallow ones, so the whole tree is needed
+    let root = build_tree(&fetch_entries(url, conf, path, None, flat)?);
+
+    let mut dirs: Vec<(String, &Node)> = Vec::new();
+    collect_dirs(&root, String::new(), 0, max_depth, &mut dirs);
+    match sort {
+        DuSort::Size => dirs.sort_by(|a, b| b.1.size.cmp(&a.1.size).then_with(|| a.0.cmp(&b.0))),
+        DuSort::Name => dirs.sort_by(|a, b| a.0.cmp(&b.0)),
+    }
+
+    let base = path.trim_end_matches('/');
+    for (rel, node) in dirs {
+        let shown = if rel.is_empty() { path.to_string() } else { format!("{}/{}", base, rel) };
+        println!("{:>12}  {}", format_size(node.size, bytes).bright_yellow(), shown.blue());
+    }
+    Ok(())
+}
+
+fn print_tree_children(node: &Node, prefix: &str, depth: usize, max_depth: Option<usize>, sizes: bool) {
+    if max_depth.is_some_and(|x| depth > x) {
+        return;
+    }
+    let count = node.children.len();
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+        let name = if child.is_dir { format!("{}/", name).blue() } else { name.normal() };
+        let size = if sizes { format!(" ({})", human_size(child.size)).bright_black().to_string() } else { String::new() };
+        println!("{}{}{}{}", prefix, if last { "└── " } else { "├── " }, name, size);
+        if child.is_dir {
+            let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            print_tree_children(child, child_prefix.as_str(), depth + 1, max_depth, sizes);
+        }
+    }
+}
+
+/// Print ASCII tree of a remote folder.
+pub fn tree(
+    url: &str,
+    conf: &config::Config,
+    path: &str,
+    max_depth: Option<usize>,
+    sizes: bool,
+    flat: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Directory sizes need everything below, otherwise stop walking at max depth
+    let walk_depth = if sizes { None } else { max_depth };
+    let root = build_tree(&fetch_entries(url, conf, path, walk_depth, flat)?);
+
+    let size = if sizes { format!(" ({})", human_size(root.size)).bright_black().to_string() } else { String::new() };
+    println!("{}{}", path.blue(), size);
+    print_tree_children(&root, "", 1, max_depth, sizes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yandex_disk_test_util::{dir, file};
+
+    fn entries(items: Vec<(&str, Resource)>) -> BTreeMap<String, Resource> {
+        items.into_iter().map(|(rel, r)| (rel.to_string(), r)).collect()
+    }
+
+    fn sizes(root: &Node, max_depth: Option<usize>) -> Vec<(String, u64)> {
+        let mut dirs = Vec::new();
+        collect_dirs(root, String::new(), 0, max_depth, &mut dirs);
+        dirs.into_iter().map(|(rel, node)| (rel, node.size)).collect()
+    }
+
+    #[test]
+    fn sizes_add_up() {
+        let root = build_tree(&entries(vec![
+            ("a", dir("disk:/a")),
+            ("a/x", file("disk:/a/x", 10, None)),
+            ("a/b", dir("disk:/a/b")),
+            ("a/b/y", file("disk:/a/b/y", 5, None)),
+            ("e", dir("disk:/e")),
+            ("z", file("disk:/z", 1, None)),
+        ]));
+        assert_eq!(root.size, 16);
+        assert!(!root.children["z"].is_dir);
+        // Deepest first, the root last
+        assert_eq!(sizes(&root, None), vec![
+            ("a/b".to_string(), 5),
+            ("a".to_string(), 15),
+            ("e".to_string(), 0),
+            (String::new(), 16),
+        ]);
+        assert_eq!(sizes(&root, Some(1)), vec![("a".to_string(), 15), ("e".to_string(), 0), (String::new(), 16)]);
+        assert_eq!(sizes(&root, Some(0)), vec![(String::new(), 16)]);
+    }
+
+    #[test]
+    fn flat_listing_makes_directories() {
+        let root = build_tree(&entries(vec![
+            ("p/q/r", file("disk:/p/q/r", 7, None)),
+            ("p/s", file("disk:/p/s", 3, None)),
+        ]));
+        assert_eq!(sizes(&root, None), vec![
+            ("p/q".to_string(), 7),
+            ("p".to_string(), 10),
+            (String::new(), 10),
+        ]);
+    }
+}
@@ -0,0 +1,157 @@
+use colored::*;
+
+use std::path::Path;
+
+use super::yandex_disk_streams::hash_file;
+use super::*;
+
+//
+// Verify local tree against remote one
+//
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Local only
+    pub missing: Vec<String>,
+    /// Remote only
+    pub extra: Vec<String>,
+    /// Present on both sides with different content
+    pub differing: Vec<String>,
+    pub matching: usize,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.differing.is_empty()
+    }
+}
+
+fn compare_file(local_path: &Path, remote: &Resource) -> Result<bool, Box<dyn std::error::Error>> {
+    // Size is known without reading the file, don't hash obviously different ones
+    if local_path.metadata()?.len() != remote.file_size() {
+        return Ok(false);
+    }
+    let local = hash_file(local_path)?;
+    match compare_checksums(&local, remote) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            debug!("{}", e);
+            Ok(false)
+        }
+    }
+}
+
+pub fn compare_trees(
+    url: &str,
+    conf: &config::Config,
+    local_path: &str,
+    remote_path: &str,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let local_base = Path::new(local_path);
+
+    if local_base.is_file() {
+        let mut report = VerifyReport::default();
+        let remote = get_resource(url, conf, remote_path, Some(CHECKSUM_FIELDS))?;
+        if compare_file(local_base, &remote)? {
+            report.matching += 1;
+        } else {
+            report.differing.push(remote_path.to_string());
+        }
+        return Ok(report);
+    }
+
+    let local = walk_local_tree(local_base)?;
+    let remote = walk_remote(url, conf, remote_path, Some(CHECKSUM_FIELDS))?;
+    compare_entries(&local, &remote)
+}
+
+/// Compare local files with remote ones, both by path relative to the compared directories
+fn compare_entries(
+    local: &BTreeMap<String, PathBuf>,
+    remote: &BTreeMap<String, Resource>,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let mut report = VerifyReport::default();
+    for (rel, path) in local.iter() {
+        match remote.get(rel) {
+            None => report.missing.push(rel.clone()),
+            Some(r) => {
+                if compare_file(path, r)? {
+                    report.matching += 1;
+                } else {
+                    report.differing.push(rel.clone());
+                }
+            }
+        }
+    }
+    report.extra = remote.keys().filter(|x| !local.contains_key(*x)).cloned().collect();
+
+    Ok(report)
+}
+
+pub fn verify(
+    url: &str,
+    conf: &config::Config,
+    local_path: &str,
+    remote_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let report = compare_trees(url, conf, local_path, remote_path)?;
+
+    for x in report.missing.iter() {
+        println!("{:10} {}", "missing".yellow(), x);
+    }
+    for x in report.extra.iter() {
+        println!("{:10} {}", "extra".blue(), x);
+    }
+    for x in report.differing.iter() {
+        println!("{:10} {}", "differs".red(), x);
+    }
+
+    info!("{} matching, {} missing, {} extra, {} differing",
+          report.matching, report.missing.len(), report.extra.len(), report.differing.len());
+
+    if report.is_clean() {
+        Ok(())
+    } else {
+        Err(IntegrityError(format!("{} and {} differ", local_path, remote_path)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yandex_disk_test_util::resource;
+
+    fn abc(path: &str, md5: &str) -> Resource {
+        resource(path, serde_json::json!({
+            "size": 3,
+            "md5": md5,
+            "sha256": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        }))
+    }
+
+    #[test]
+    fn trees() {
+        let dir = std::env::temp_dir().join(format!("yadisk-client-verify-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["same.txt", "sub/changed.txt", "local.txt"] {
+            std::fs::write(dir.join(name), "abc").unwrap();
+        }
+        std::fs::write(dir.join("resized.txt"), "abcd").unwrap();
+        let local = walk_local_tree(&dir).unwrap();
+
+        let remote: BTreeMap<String, Resource> = vec![
+            ("same.txt", abc("disk:/x/same.txt", "900150983cd24fb0d6963f7d28e17f72")),
+            ("sub/changed.txt", abc("disk:/x/sub/changed.txt", "00000000000000000000000000000000")),
+            ("resized.txt", abc("disk:/x/resized.txt", "900150983cd24fb0d6963f7d28e17f72")),
+            ("remote.txt", abc("disk:/x/remote.txt", "900150983cd24fb0d6963f7d28e17f72")),
+        ].into_iter().map(|(rel, r)| (rel.to_string(), r)).collect();
+
+        let report = compare_entries(&local, &remote).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(report.matching, 1);
+        assert_eq!(report.missing, vec!["local.txt"]);
+        assert_eq!(report.extra, vec!["remote.txt"]);
+        assert_eq!(report.differing, vec!["resized.txt", "sub/changed.txt"]);
+        assert!(!report.is_clean());
+    }
+}